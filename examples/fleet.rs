@@ -0,0 +1,102 @@
+//! Fleet aggregation: several hosts push snapshots of the same series to a collector,
+//! which merges them and attributes the tail to the hosts that caused it.
+//!
+//! After the first full push, hosts send only the buckets that changed since the
+//! collector's last acknowledged snapshot.
+//!
+//! Run with `cargo run --example fleet`.
+
+use quantile::{Fraction, Provenance, Snapshot, SnapshotDelta, TimeBasedRingBuffer};
+
+struct Host {
+    name: &'static str,
+    buffer: TimeBasedRingBuffer,
+    /// Last snapshot the collector acknowledged, if any.
+    acknowledged: Option<Snapshot>,
+    slow: bool,
+}
+
+impl Host {
+    fn new(name: &'static str, slow: bool) -> Self {
+        Host {
+            name,
+            buffer: TimeBasedRingBuffer::new(6, 10, 0, 2_000),
+            acknowledged: None,
+            slow,
+        }
+    }
+
+    fn serve(&mut self, from: u64, to: u64) {
+        for ts in from..to {
+            let latency = if self.slow && ts % 4 == 0 {
+                1_500
+            } else {
+                20 + ts % 30
+            };
+            self.buffer.insert(latency, ts).unwrap();
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let provenance = Provenance {
+            hostname: self.name.to_string(),
+            ..Provenance::current(0)
+        };
+        self.buffer.snapshot().with_provenance(provenance)
+    }
+}
+
+fn main() {
+    let mut hosts = [
+        Host::new("web-1", false),
+        Host::new("web-2", true),
+        Host::new("web-3", false),
+    ];
+    // What the collector holds for each host
+    let mut received: Vec<Option<Snapshot>> = vec![None; hosts.len()];
+
+    for round in 0..3u64 {
+        let mut pushed = 0;
+        for (host, received) in hosts.iter_mut().zip(&mut received) {
+            host.serve(round * 20, round * 20 + 20);
+            let current = host.snapshot();
+            let update = match (&host.acknowledged, received.as_ref()) {
+                (Some(base), Some(held)) => {
+                    let sent = current.delta_since(base).unwrap().to_parts();
+                    // Crosses the network as raw parts and is checked on arrival
+                    let delta = SnapshotDelta::from_parts(sent).unwrap();
+                    pushed += delta.changed_buckets();
+                    // A receiver that lost track would ask for a full snapshot here
+                    held.apply_delta(&delta).unwrap_or_else(|_| current.clone())
+                }
+                _ => {
+                    pushed += current
+                        .to_parts()
+                        .iter()
+                        .map(|w| w.counts.len())
+                        .sum::<usize>();
+                    current.clone()
+                }
+            };
+            *received = Some(update);
+            host.acknowledged = Some(current);
+        }
+        println!("round {round}: pushed {pushed} buckets");
+    }
+
+    // Index each host's distribution before merging, to attribute the tail afterwards
+    let mut snapshots = received
+        .into_iter()
+        .flatten()
+        .map(Snapshot::with_contributor_index);
+    let mut fleet = snapshots.next().unwrap();
+    for snapshot in snapshots {
+        fleet.merge(&snapshot).unwrap();
+    }
+    let fractions = [Fraction::P50, Fraction::P99];
+    print!("{}", fleet.table(&fractions).unwrap());
+    println!("Hosts contributing values above 1s:");
+    for (host, count) in fleet.top_contributors_above(1_000).unwrap() {
+        println!("  {} ({count} values)", host.hostname);
+    }
+}
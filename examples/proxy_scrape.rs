@@ -0,0 +1,114 @@
+//! Scrapes histogram buckets from a proxy's stats endpoint with `quantile::proxy` and
+//! prints the quantiles of what each histogram recorded between scrapes.
+//!
+//! Envoy's `/stats?histogram_buckets=cumulative` and Prometheus `_bucket` samples, as
+//! exported by NGINX or Envoy's `/stats/prometheus`, are understood.
+//!
+//! Run with `cargo run --features proxy --example proxy_scrape` to parse built-in sample
+//! scrapes, or point it at a live endpoint: `cargo run --features proxy --example
+//! proxy_scrape -- 127.0.0.1:9901 "/stats?histogram_buckets=cumulative" 1000`. The last
+//! argument converts bounds to the recording unit, e.g. 1 for Envoy's milliseconds or
+//! 1000 for seconds to milliseconds.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use quantile::proxy::{self, ProxyScraper};
+use quantile::{Fraction, QuantileRegistry, SeriesConfig};
+
+const ENVOY_BEFORE: &str = "\
+cluster.api.upstream_rq_time: B0.5(0,2) B1(0,5) B5(0,40) B10(0,80) B25(0,95) B50(0,99) B100(0,100)
+cluster.api.upstream_cx_length_ms: No recorded values
+";
+
+const ENVOY_AFTER: &str = "\
+cluster.api.upstream_rq_time: B0.5(1,3) B1(4,10) B5(45,90) B10(30,160) B25(12,187) B50(6,197) B100(2,200)
+";
+
+const NGINX_BEFORE: &str = "\
+# TYPE nginx_vts_upstream_request_duration_seconds histogram
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.005\"} 10
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.05\"} 60
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.5\"} 95
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"+Inf\"} 100
+nginx_vts_upstream_request_duration_seconds_count{upstream=\"api\"} 100
+";
+
+const NGINX_AFTER: &str = "\
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.005\"} 20
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.05\"} 100
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"0.5\"} 190
+nginx_vts_upstream_request_duration_seconds_bucket{upstream=\"api\",le=\"+Inf\"} 200
+";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let [address, path, scale] = &args[..] {
+        let mut scraper = ProxyScraper::new(scale.parse()?)?;
+        let mut registry = registry();
+        for timestamp in (0..).step_by(10) {
+            let ingested =
+                scraper.ingest(&proxy::fetch(address, path)?, &mut registry, timestamp)?;
+            report(&registry, &ingested);
+            thread::sleep(Duration::from_secs(10));
+        }
+    }
+    println!("Envoy, bounds in milliseconds:");
+    scrape_pair(ENVOY_BEFORE, ENVOY_AFTER, 1.0)?;
+    println!("NGINX, bounds in seconds recorded as milliseconds:");
+    scrape_pair(NGINX_BEFORE, NGINX_AFTER, 1000.0)?;
+    Ok(())
+}
+
+/// One-minute series of ten-second windows over values up to a minute in milliseconds.
+fn registry() -> QuantileRegistry {
+    QuantileRegistry::builder(SeriesConfig {
+        capacity: 6,
+        duration: 10,
+        start: 0,
+        end: 60_000,
+    })
+    .build()
+}
+
+/// Ingests two scrapes taken ten seconds apart and prints what was recorded in between.
+fn scrape_pair(before: &str, after: &str, scale: f64) -> Result<(), &'static str> {
+    let mut scraper = ProxyScraper::new(scale)?;
+    let mut registry = registry();
+    scraper.ingest(before, &mut registry, 0)?;
+    let ingested = scraper.ingest(after, &mut registry, 10)?;
+    report(&registry, &ingested);
+    Ok(())
+}
+
+/// Prints the latest window's quantiles of each series.
+fn report(registry: &QuantileRegistry, ingested: &proxy::Ingested) {
+    let fractions = [Fraction::P50, Fraction::P90, Fraction::P99];
+    for key in registry.keys() {
+        let latest = registry.get(key).unwrap().windows().last();
+        let Some((_, latest)) = latest.filter(|(_, w)| w.max().is_some()) else {
+            println!("  {key}: no values this interval");
+            continue;
+        };
+        let values: Vec<String> = fractions
+            .iter()
+            .zip(latest.estimate_quantiles(&fractions).unwrap())
+            .map(|(fraction, value)| format!("{fraction}={value}"))
+            .collect();
+        println!(
+            "  {key}: {} ({} values)",
+            values.join(" "),
+            latest.rank(u64::MAX)
+        );
+    }
+    if ingested.above_last_bound > 0 {
+        println!(
+            "  {} above the last bound, counted at it",
+            ingested.above_last_bound
+        );
+    }
+    for (key, reason) in &ingested.rejected {
+        println!("  {key}: skipped, {reason}");
+    }
+}
@@ -0,0 +1,65 @@
+//! Replays a recorded log of `timestamp value` lines into a ring buffer and prints the
+//! percentiles of every window.
+//!
+//! Run with `cargo run --example replay -- latencies.log "p50,p99,max"`, or pipe the log
+//! through stdin by passing `-` as the path. Without arguments, a short built-in log
+//! is replayed.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::process;
+
+use quantile::{TimeBasedRingBuffer, parse_quantiles};
+
+const SAMPLE: &str = "0 12\n1 15\n3 11\n7 240\n12 14\n15 13\n18 16\n22 900\n25 12\n";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let input: Box<dyn BufRead> = match args.first().map(String::as_str) {
+        None => Box::new(Cursor::new(SAMPLE)),
+        Some("-") => Box::new(BufReader::new(io::stdin())),
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => fail(&format!("Cannot open {path}: {e}")),
+        },
+    };
+    let spec = args.get(1).map_or("p50,p90,p99", String::as_str);
+    let fractions = parse_quantiles(spec).unwrap_or_else(|e| fail(&e.to_string()));
+
+    // Ten windows of ten seconds over latencies of up to ten seconds in milliseconds
+    let mut ring_buffer = TimeBasedRingBuffer::new(10, 10, 0, 10_000);
+    for (number, line) in input.lines().enumerate() {
+        let line = line.unwrap_or_else(|e| fail(&e.to_string()));
+        let mut fields = line.split_whitespace();
+        let (Some(timestamp), Some(value), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            eprintln!("line {}: expected `timestamp value`", number + 1);
+            continue;
+        };
+        let parsed = timestamp.parse().ok().zip(value.parse().ok());
+        let result = match parsed {
+            Some((timestamp, value)) => ring_buffer.insert(value, timestamp),
+            None => Err("Invalid number"),
+        };
+        if let Err(e) = result {
+            eprintln!("line {}: {e}", number + 1);
+        }
+    }
+
+    let snapshot = ring_buffer.snapshot();
+    for (start, window) in snapshot.windows() {
+        if let Ok(values) = window.estimate_quantiles(&fractions) {
+            println!("[{start}, {}) {values:?}", start + snapshot.duration());
+        }
+    }
+    match snapshot.table(&fractions) {
+        Ok(table) => print!("{table}"),
+        Err(e) => fail(e),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}");
+    process::exit(1)
+}
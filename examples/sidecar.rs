@@ -0,0 +1,73 @@
+//! A sidecar exposing quantiles of a simulated workload on a Prometheus endpoint.
+//!
+//! Run with `cargo run --example sidecar`, then `curl http://127.0.0.1:9464/metrics`.
+//! Pass a number to exit after serving that many requests, e.g. `-- 1`.
+
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use quantile::{ExportFilter, NAME_LABEL, QuantileRegistry, SeriesConfig};
+
+const ADDRESS: &str = "127.0.0.1:9464";
+
+fn main() -> std::io::Result<()> {
+    let limit: Option<usize> = env::args().nth(1).and_then(|n| n.parse().ok());
+    let config = SeriesConfig {
+        capacity: 12,
+        duration: 5,
+        start: 0,
+        end: 5_000,
+    };
+    let registry = Arc::new(Mutex::new(QuantileRegistry::builder(config).build()));
+    let started = Instant::now();
+
+    // Stand-in for the proxied service: a few requests per millisecond on two routes
+    let recorder = Arc::clone(&registry);
+    thread::spawn(move || {
+        for i in 0u64.. {
+            let (key, millis) = match i % 3 {
+                0 => ("http_latency{route=\"/checkout\"}", 80 + i * 13 % 400),
+                1 => ("http_latency{route=\"/cart\"}", 10 + i * 7 % 60),
+                _ => ("healthcheck", 1),
+            };
+            let now = started.elapsed().as_secs();
+            recorder.lock().unwrap().record(key, millis, now).unwrap();
+            thread::sleep(Duration::from_micros(300));
+        }
+    });
+
+    let filter = ExportFilter::new().exclude(NAME_LABEL, "healthcheck*");
+    let listener = TcpListener::bind(ADDRESS)?;
+    println!("Serving http://{ADDRESS}/metrics");
+    for (served, stream) in listener.incoming().enumerate() {
+        let body = registry
+            .lock()
+            .unwrap()
+            .prometheus_summary(&[0.5, 0.9, 0.99], &filter)
+            .expect("fractions are in range");
+        respond(stream?, &body)?;
+        if limit.is_some_and(|limit| served + 1 >= limit) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, body: &str) -> std::io::Result<()> {
+    // Drain the request headers; every path serves the metrics
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
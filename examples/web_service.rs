@@ -0,0 +1,84 @@
+//! Request latencies recorded from many worker threads through a single recorder actor.
+//!
+//! Workers send `(route, latency)` messages over a channel and never touch the registry,
+//! so the hot path is one channel send. The actor owns the registry and prints a
+//! percentile table per route once the workers are done.
+//!
+//! Run with `cargo run --example web_service`.
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use quantile::{ExportFilter, Fraction, NewSeries, QuantileRegistry, SeriesConfig};
+
+enum Message {
+    Latency { route: String, millis: u64 },
+    Shutdown,
+}
+
+fn main() {
+    let (sender, receiver) = mpsc::channel();
+    let started = Instant::now();
+
+    let actor = thread::spawn(move || {
+        let config = SeriesConfig {
+            capacity: 60,
+            duration: 1,
+            start: 0,
+            end: 10_000,
+        };
+        // Collapse numeric path segments so `/users/42` and `/users/7` share a series
+        let mut registry = QuantileRegistry::builder(config)
+            .on_new_series(|key, config| {
+                let route: Vec<&str> = key
+                    .split('/')
+                    .map(|s| if s.parse::<u64>().is_ok() { "{id}" } else { s })
+                    .collect();
+                NewSeries::Create {
+                    key: route.join("/"),
+                    config,
+                }
+            })
+            .build();
+        for message in receiver {
+            match message {
+                Message::Latency { route, millis } => {
+                    let now = started.elapsed().as_secs();
+                    if let Err(e) = registry.record(&route, millis, now) {
+                        eprintln!("Dropped {route}: {e}");
+                    }
+                }
+                Message::Shutdown => break,
+            }
+        }
+        registry
+    });
+
+    let workers: Vec<_> = (0..4u64)
+        .map(|worker| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for request in 0..2_500u64 {
+                    let (route, millis) = match request % 4 {
+                        0 => (format!("/users/{request}"), 5 + request % 20),
+                        1 => ("/search".to_string(), 40 + (request * 7 + worker) % 300),
+                        _ => ("/health".to_string(), 1),
+                    };
+                    sender.send(Message::Latency { route, millis }).unwrap();
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    sender.send(Message::Shutdown).unwrap();
+    let registry = actor.join().unwrap();
+
+    let fractions = [Fraction::P50, Fraction::P99, Fraction::MAX];
+    for (route, snapshot) in registry.snapshots(&ExportFilter::new()) {
+        println!("{route}");
+        print!("{}", snapshot.table(&fractions).unwrap());
+    }
+}
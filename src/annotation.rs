@@ -0,0 +1,100 @@
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// A marker attached to a point in time, such as a deploy or an incident, kept alongside
+/// the windows so latency shifts can be correlated with events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation {
+    pub timestamp: u64,
+    pub text: String,
+    pub labels: Vec<(String, String)>,
+}
+
+impl Annotation {
+    pub fn new(timestamp: u64, text: impl Into<String>) -> Self {
+        Annotation {
+            timestamp,
+            text: text.into(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Adds a key-value label, e.g. `("version", "1.2.3")`.
+    pub fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl TimeBasedRingBuffer {
+    /// Attaches a text annotation at `timestamp`, e.g. `annotate(ts, "deploy v1.2.3")`.
+    pub fn annotate(&mut self, timestamp: u64, text: impl Into<String>) {
+        self.add_annotation(Annotation::new(timestamp, text));
+    }
+
+    /// Attaches an annotation. Annotations are dropped once their window is evicted.
+    pub fn add_annotation(&mut self, annotation: Annotation) {
+        let at = self
+            .annotations
+            .partition_point(|a| a.timestamp <= annotation.timestamp);
+        self.annotations.insert(at, annotation);
+    }
+
+    /// Returns the retained annotations, ordered by timestamp.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+}
+
+impl Snapshot {
+    /// Returns the annotations of the snapshotted windows, ordered by timestamp.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+}
+
+/// Returns the annotations falling in `[start, start + duration)` of a sorted slice.
+pub(crate) fn annotations_in(
+    annotations: &[Annotation],
+    start: u64,
+    duration: u64,
+) -> &[Annotation] {
+    let end = start.saturating_add(duration);
+    let first = annotations.partition_point(|a| a.timestamp < start);
+    let last = annotations.partition_point(|a| a.timestamp < end);
+    &annotations[first..last]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_annotations_follow_windows() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, 10, 0, 100);
+        ring_buffer.insert(5, 0).unwrap();
+        ring_buffer.annotate(3, "deploy v1.2.3");
+        ring_buffer.add_annotation(Annotation::new(1, "incident").with_label("sev", "2"));
+        ring_buffer.insert(50, 12).unwrap();
+        ring_buffer.annotate(15, "rollback");
+        let texts: Vec<&str> = ring_buffer
+            .annotations()
+            .iter()
+            .map(|a| a.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["incident", "deploy v1.2.3", "rollback"]);
+
+        let bands = ring_buffer.bands(0.0, 0.5, 1.0).unwrap();
+        assert_eq!(bands[0].annotations.len(), 2);
+        assert_eq!(bands[1].annotations[0].text, "rollback");
+        assert_eq!(ring_buffer.snapshot().annotations().len(), 3);
+
+        // Evicting the first window drops its annotations
+        ring_buffer.insert(7, 25).unwrap();
+        let texts: Vec<&str> = ring_buffer
+            .annotations()
+            .iter()
+            .map(|a| a.text.as_str())
+            .collect();
+        assert_eq!(texts, vec!["rollback"]);
+    }
+}
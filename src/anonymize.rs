@@ -0,0 +1,179 @@
+use crate::domain::MAX_BUCKETS;
+use crate::estimator::QuantileEstimator;
+use crate::snapshot::Snapshot;
+
+/// Prepares a snapshot for sharing outside the organization, e.g. with a vendor or in a
+/// public issue report, by hiding the absolute numbers behind the distribution.
+///
+/// Contributors, annotations and the audit log are always removed, since they name
+/// hosts, deploys and series. Values can be rescaled by a secret factor, and counts
+/// perturbed with Laplace noise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anonymizer {
+    factor: f64,
+    noise: Option<(f64, u64)>,
+}
+
+impl Default for Anonymizer {
+    fn default() -> Self {
+        Anonymizer {
+            factor: 1.0,
+            noise: None,
+        }
+    }
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer that only strips metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Multiplies every value, and the range, by `factor`, rounding to the nearest
+    /// integer. Shapes and ratios between quantiles are kept; absolute values are not.
+    pub fn scale(mut self, factor: f64) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    /// Adds Laplace noise of scale `1 / epsilon` to every non-empty bucket, clamping at
+    /// zero, from a generator seeded with `seed`. Smaller `epsilon` hides exact counts
+    /// better at the cost of accuracy.
+    ///
+    /// This masks counts but not which values occurred, so it does not by itself give
+    /// differential privacy.
+    pub fn noise(mut self, epsilon: f64, seed: u64) -> Self {
+        self.noise = Some((epsilon, seed));
+        self
+    }
+
+    /// Returns an anonymized copy of `snapshot`.
+    pub fn apply(&self, snapshot: &Snapshot) -> Result<Snapshot, &'static str> {
+        if !(self.factor.is_finite() && self.factor > 0.0) {
+            return Err("Scale factor must be positive and finite");
+        }
+        let mut rng = match self.noise {
+            Some((epsilon, seed)) if epsilon.is_finite() && epsilon > 0.0 => {
+                Some((1.0 / epsilon, SplitMix(seed)))
+            }
+            Some(_) => return Err("Noise epsilon must be positive and finite"),
+            None => None,
+        };
+        let scaled = |value: u64| -> Result<u64, &'static str> {
+            let scaled = (value as f64 * self.factor).round();
+            if scaled >= u64::MAX as f64 {
+                return Err("Scaled values overflow");
+            }
+            Ok(scaled as u64)
+        };
+        let (start, end) = (scaled(snapshot.start)?, scaled(snapshot.end)?);
+        if end - start >= MAX_BUCKETS {
+            return Err("Scaled range is too wide");
+        }
+        let len = (end - start + 1) as usize;
+        let mut windows = Vec::with_capacity(snapshot.windows.len());
+        for (window_start, window) in &snapshot.windows {
+            let mut counts = vec![0; len];
+            for (i, &count) in window.quantiles.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                let value = scaled(snapshot.start + i as u64)?;
+                let count = match &mut rng {
+                    Some((scale, rng)) => {
+                        (count as f64 + rng.laplace(*scale)).round().max(0.0) as usize
+                    }
+                    None => count,
+                };
+                counts[(value - start) as usize] += count;
+            }
+            let estimator = QuantileEstimator::from_counts(start, end, counts);
+            windows.push((*window_start, estimator));
+        }
+        Ok(Snapshot {
+            start,
+            end,
+            duration: snapshot.duration,
+            windows,
+            contributors: Vec::new(),
+            contributor_index: None,
+            annotations: Vec::new(),
+            audit: Vec::new(),
+        })
+    }
+}
+
+impl Snapshot {
+    /// Returns a copy safe to share outside the organization. See [`Anonymizer`].
+    pub fn anonymized(&self, anonymizer: &Anonymizer) -> Result<Snapshot, &'static str> {
+        anonymizer.apply(self)
+    }
+}
+
+/// SplitMix64, a small generator whose output is the same on every platform, so noisy
+/// snapshots are reproducible from their seed.
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Samples a Laplace distribution centered on zero by inverting its CDF.
+    fn laplace(&mut self, scale: f64) -> f64 {
+        // Uniform in (-0.5, 0.5), never reaching either end
+        let u = ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64 - 0.5;
+        -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provenance::Provenance;
+    use crate::ring_buffer::TimeBasedRingBuffer;
+    #[test]
+    fn test_anonymized() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+        for v in 1..=100 {
+            ring_buffer.insert(v * 3, v / 10).unwrap();
+        }
+        ring_buffer.annotate(5, "deploy billing-7f3a");
+        let snapshot = ring_buffer
+            .snapshot()
+            .with_provenance(Provenance::current(0));
+
+        let shared = snapshot.anonymized(&Anonymizer::new().scale(2.5)).unwrap();
+        assert!(shared.contributors().is_empty());
+        assert!(shared.annotations().is_empty());
+        assert_eq!(shared.estimate_quantile(0.5).unwrap(), 375);
+        assert_eq!(shared.combined().val_count, snapshot.combined().val_count);
+        assert!(shared.validate().is_valid());
+
+        let noisy = Anonymizer::new().scale(0.5).noise(2.0, 7);
+        let a = snapshot.anonymized(&noisy).unwrap();
+        let b = snapshot.anonymized(&noisy).unwrap();
+        assert_eq!(a.to_parts(), b.to_parts());
+        assert_ne!(
+            a.to_parts(),
+            snapshot
+                .anonymized(&Anonymizer::new().scale(0.5))
+                .unwrap()
+                .to_parts()
+        );
+        let median = a.estimate_quantile(0.5).unwrap() as i64;
+        assert!((median - 75).abs() <= 10, "median {median}");
+
+        assert!(snapshot.anonymized(&Anonymizer::new().scale(0.0)).is_err());
+        assert!(snapshot.anonymized(&Anonymizer::new().scale(1e20)).is_err());
+        assert!(
+            snapshot
+                .anonymized(&Anonymizer::new().noise(-1.0, 0))
+                .is_err()
+        );
+    }
+}
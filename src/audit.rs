@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+
+use crate::registry::QuantileRegistry;
+use crate::ring_buffer::{ShrinkPolicy, TimeBasedRingBuffer};
+use crate::snapshot::Snapshot;
+
+/// Number of entries kept per audit log; older ones are dropped first.
+const AUDIT_CAPACITY: usize = 64;
+
+/// A change the crate made to its own behavior at runtime, kept so that a shift in
+/// accuracy or retention can be explained after the fact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the change happened, on the clock of the values being recorded.
+    pub timestamp: u64,
+    pub event: AuditEvent,
+}
+
+/// The kinds of change recorded in an audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuditEvent {
+    /// A ring buffer's retention changed.
+    Resized {
+        from: usize,
+        to: usize,
+        policy: ShrinkPolicy,
+    },
+    /// A registry shortened every series' retention to stay under its memory cap.
+    RetentionShortened { capacity: usize },
+    /// A registry evicted a series to stay under its memory cap.
+    SeriesEvicted { key: String },
+}
+
+/// Appends `event` to a bounded log, dropping the oldest entry when full.
+pub(crate) fn push(log: &mut VecDeque<AuditEntry>, timestamp: u64, event: AuditEvent) {
+    if log.len() == AUDIT_CAPACITY {
+        log.pop_front();
+    }
+    log.push_back(AuditEntry { timestamp, event });
+}
+
+impl TimeBasedRingBuffer {
+    /// Returns the most recent runtime changes to this buffer, oldest first.
+    pub fn audit_log(&self) -> impl ExactSizeIterator<Item = &AuditEntry> {
+        self.audit.iter()
+    }
+}
+
+impl QuantileRegistry {
+    /// Returns the most recent degradations applied to the registry as a whole, oldest
+    /// first. Changes to a single series are in that series' own log.
+    pub fn audit_log(&self) -> impl ExactSizeIterator<Item = &AuditEntry> {
+        self.audit.iter()
+    }
+}
+
+impl Snapshot {
+    /// Returns the audit log of the snapshotted buffers, ordered by timestamp.
+    pub fn audit_log(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    #[test]
+    fn test_audit_log() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 10, 0, 100);
+        ring_buffer.insert(1, 25).unwrap();
+        ring_buffer.resize(2, ShrinkPolicy::Drop).unwrap();
+        let entries: Vec<&AuditEntry> = ring_buffer.audit_log().collect();
+        assert_eq!(
+            entries,
+            vec![&AuditEntry {
+                timestamp: 20,
+                event: AuditEvent::Resized {
+                    from: 4,
+                    to: 2,
+                    policy: ShrinkPolicy::Drop
+                },
+            }]
+        );
+        let mut snapshot = ring_buffer.snapshot();
+        assert_eq!(snapshot.audit_log().len(), 1);
+        snapshot.merge(&ring_buffer.snapshot()).unwrap();
+        assert_eq!(snapshot.audit_log().len(), 2);
+
+        for _ in 0..AUDIT_CAPACITY {
+            ring_buffer.resize(3, ShrinkPolicy::Drop).unwrap();
+        }
+        assert_eq!(ring_buffer.audit_log().len(), AUDIT_CAPACITY);
+
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 1023,
+        };
+        let mut single = QuantileRegistry::builder(config).build();
+        single.record("a", 1, 0).unwrap();
+        let cap = single.memory_usage() * 3 / 2;
+        let mut registry = QuantileRegistry::builder(config).memory_cap(cap).build();
+        registry.record("a", 1, 0).unwrap();
+        registry.record("b", 1, 10).unwrap();
+        registry.record("c", 1, 20).unwrap();
+        let events: Vec<&AuditEvent> = registry.audit_log().map(|e| &e.event).collect();
+        assert_eq!(
+            events,
+            vec![
+                &AuditEvent::RetentionShortened { capacity: 1 },
+                &AuditEvent::SeriesEvicted {
+                    key: "a".to_string()
+                },
+            ]
+        );
+        assert_eq!(registry.audit_log().last().unwrap().timestamp, 20);
+        assert_eq!(registry.get("b").unwrap().audit_log().len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Per-iteration timing for benchmark harnesses.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::estimator::QuantileEstimator;
+use crate::fraction::{IntoFraction, checked};
+use crate::snapshot::percentile_label;
+
+/// Number of slowest iterations kept for the summary.
+const OUTLIERS: usize = 5;
+
+/// Collects iteration durations and keeps the slowest iterations, so microbenchmarks
+/// can report tail latency rather than only the mean.
+#[derive(Debug, Clone)]
+pub struct Collector {
+    estimator: QuantileEstimator,
+    resolution: Duration,
+    iterations: usize,
+    clamped: usize,
+    outliers: Vec<(usize, u64)>,
+}
+
+impl Collector {
+    /// Creates a collector for durations up to `max`, with one bucket per `resolution`,
+    /// under the same limits as [`QuantileEstimator::for_latency`]. Longer iterations are
+    /// counted at `max` and reported as clamped.
+    pub fn new(max: Duration, resolution: Duration) -> Result<Self, &'static str> {
+        Ok(Collector {
+            estimator: QuantileEstimator::for_latency(max, resolution)?,
+            resolution,
+            iterations: 0,
+            clamped: 0,
+            outliers: Vec::with_capacity(OUTLIERS + 1),
+        })
+    }
+
+    /// Records the duration of one iteration.
+    pub fn record(&mut self, elapsed: Duration) {
+        let units = QuantileEstimator::duration_in(elapsed, self.resolution);
+        if units > self.estimator.end {
+            self.clamped += 1;
+        }
+        let _ = self.estimator.add_value(units.min(self.estimator.end));
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let at = self.outliers.partition_point(|&(_, slow)| slow >= nanos);
+        if at < OUTLIERS {
+            self.outliers.insert(at, (self.iterations, nanos));
+            self.outliers.truncate(OUTLIERS);
+        }
+        self.iterations += 1;
+    }
+
+    /// Runs `f` once, recording how long it took, and returns its result.
+    pub fn measure<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.record(started.elapsed());
+        result
+    }
+
+    /// Runs `f` `iters` times, recording each iteration, and returns the total time, as
+    /// criterion's `iter_custom` expects.
+    pub fn time_iters<T>(&mut self, iters: u64, mut f: impl FnMut() -> T) -> Duration {
+        let mut total = Duration::ZERO;
+        for _ in 0..iters {
+            let started = Instant::now();
+            std::hint::black_box(f());
+            let elapsed = started.elapsed();
+            self.record(elapsed);
+            total += elapsed;
+        }
+        total
+    }
+
+    /// Returns the number of iterations recorded.
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Returns the slowest iterations as `(iteration, nanoseconds)`, slowest first.
+    pub fn outliers(&self) -> &[(usize, u64)] {
+        &self.outliers
+    }
+
+    /// Returns the estimator holding every recorded duration, in units of the resolution.
+    pub fn estimator(&self) -> &QuantileEstimator {
+        &self.estimator
+    }
+
+    /// Renders the iteration count, one line per fraction and the slowest iterations.
+    /// Quantiles are the lower bound of their bucket, in nanoseconds.
+    pub fn summary(&self, fractions: &[impl IntoFraction]) -> Result<String, &'static str> {
+        let fractions = checked(fractions)?;
+        let values = self.estimator.estimate_quantiles(&fractions)?;
+        let mut out = format!("iterations: {}\n", self.iterations);
+        if self.clamped > 0 {
+            let _ = writeln!(
+                out,
+                "clamped: {} above {}ns",
+                self.clamped,
+                self.nanos(self.estimator.end)
+            );
+        }
+        for (&fraction, value) in fractions.iter().zip(values) {
+            let nanos = self.nanos(value);
+            let _ = writeln!(out, "{}: {nanos}ns", percentile_label(fraction));
+        }
+        let slowest: Vec<String> = self
+            .outliers
+            .iter()
+            .map(|(iteration, nanos)| format!("#{iteration} {nanos}ns"))
+            .collect();
+        let _ = writeln!(out, "slowest: {}", slowest.join(", "));
+        Ok(out)
+    }
+
+    /// Converts a bucket back to nanoseconds.
+    fn nanos(&self, units: u64) -> u128 {
+        units as u128 * self.resolution.as_nanos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_collector_summary() {
+        let ns = Duration::from_nanos(1);
+        let mut collector = Collector::new(Duration::from_micros(10), ns).unwrap();
+        for nanos in [100, 200, 300, 9_000, 400, 50_000, 500, 600, 700, 800] {
+            collector.record(Duration::from_nanos(nanos));
+        }
+        assert_eq!(collector.iterations(), 10);
+        assert_eq!(collector.outliers()[0], (5, 50_000));
+        assert_eq!(collector.outliers()[1], (3, 9_000));
+        assert_eq!(collector.outliers().len(), 5);
+        assert_eq!(
+            collector.summary(&[0.5, 1.0]).unwrap(),
+            "iterations: 10\n\
+             clamped: 1 above 10000ns\n\
+             p50: 500ns\n\
+             p100: 10000ns\n\
+             slowest: #5 50000ns, #3 9000ns, #9 800ns, #8 700ns, #7 600ns\n"
+        );
+        assert_eq!(collector.measure(|| 7), 7);
+        collector.time_iters(3, || ());
+        assert_eq!(collector.iterations(), 14);
+
+        let ms = Duration::from_millis(1);
+        let mut coarse = Collector::new(Duration::from_secs(3600), ms).unwrap();
+        coarse.record(Duration::from_micros(2_750));
+        assert!(coarse.summary(&[0.5]).unwrap().contains("p50: 2000000ns"));
+        assert!(coarse.summary(&[f64::NAN]).is_err());
+        assert_eq!(coarse.outliers()[0], (0, 2_750_000));
+        assert!(Collector::new(Duration::from_secs(1), ns).is_err());
+        assert!(Collector::new(Duration::MAX, ns).is_err());
+        assert!(Collector::new(Duration::from_secs(1), Duration::ZERO).is_err());
+    }
+}
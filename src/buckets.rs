@@ -0,0 +1,159 @@
+use crate::domain::MAX_BUCKETS;
+use crate::estimator::QuantileEstimator;
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+impl QuantileEstimator {
+    /// Builds an estimator from a histogram binned elsewhere, such as Prometheus classic
+    /// histogram buckets or Envoy stats.
+    ///
+    /// `boundaries` are the inclusive upper bounds of the buckets in increasing order,
+    /// Prometheus' `le` labels without `+Inf`, and `counts` the number of values in each
+    /// bucket (not cumulative). The first bucket starts at zero. Each bucket's count is
+    /// spread evenly over the values it covers, so quantiles interpolate linearly inside a
+    /// bucket as Prometheus' `histogram_quantile` does, and are exact at bucket bounds.
+    /// Reports on the estimator bound each quantile by the bucket it falls in.
+    ///
+    /// Every value up to the last boundary gets its own bucket, so boundaries in
+    /// nanoseconds or bytes usually need dividing into coarser units first. Fails if the
+    /// last boundary is not below the cap of the domain constructors, 2^24.
+    pub fn from_buckets(boundaries: &[u64], counts: &[usize]) -> Result<Self, &'static str> {
+        let spread = spread(boundaries, counts)?;
+        let end = spread.len() as u64 - 1;
+        let mut estimator = QuantileEstimator::from_counts(0, end, spread);
+        estimator.add_spread(&spread_ranges(boundaries, counts));
+        Ok(estimator)
+    }
+}
+
+impl TimeBasedRingBuffer {
+    /// Adds a histogram binned elsewhere to the window of `timestamp`, spreading each
+    /// bucket's count over the values it covers as
+    /// [`QuantileEstimator::from_buckets`] does. `counts` are the values recorded since
+    /// the previous call, not cumulative totals.
+    ///
+    /// Fails without recording anything if a non-empty bucket reaches outside the
+    /// buffer's range.
+    pub fn insert_buckets(
+        &mut self,
+        boundaries: &[u64],
+        counts: &[usize],
+        timestamp: u64,
+    ) -> Result<(), &'static str> {
+        let spread = spread(boundaries, counts)?;
+        let first = spread.iter().position(|&c| c > 0);
+        let last = spread.iter().rposition(|&c| c > 0);
+        let mut low = 0;
+        for (&high, &count) in boundaries.iter().zip(counts) {
+            if count > 0 && (low < self.start || high > self.end) {
+                return Err("Bucket outside the buffer's range");
+            }
+            low = high + 1;
+        }
+        self.advance(timestamp)?;
+        let (Some(first), Some(last)) = (first, last) else {
+            return Ok(());
+        };
+        let mut values = vec![0; (self.end - self.start + 1) as usize];
+        let offset = self.start as usize;
+        values[first - offset..=last - offset].copy_from_slice(&spread[first..=last]);
+        let mut window = QuantileEstimator::from_counts(self.start, self.end, values);
+        window.add_spread(&spread_ranges(boundaries, counts));
+        self.windows[self.current].merge(&window)
+    }
+}
+
+/// Inclusive value ranges of the non-empty buckets wider than one value.
+fn spread_ranges(boundaries: &[u64], counts: &[usize]) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut low = 0;
+    for (&high, &count) in boundaries.iter().zip(counts) {
+        if count > 0 && high > low {
+            ranges.push((low, high));
+        }
+        low = high + 1;
+    }
+    ranges
+}
+
+/// Spreads each bucket's count evenly over the values it covers, returning one count per
+/// value from zero to the last boundary.
+fn spread(boundaries: &[u64], counts: &[usize]) -> Result<Vec<usize>, &'static str> {
+    if boundaries.len() != counts.len() {
+        return Err("Need one count per bucket boundary");
+    }
+    if boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("Bucket boundaries must be strictly increasing");
+    }
+    let Some(&end) = boundaries.last() else {
+        return Err("No buckets given");
+    };
+    if end >= MAX_BUCKETS {
+        return Err("Bucket range too wide for unit buckets, rescale the boundaries");
+    }
+    if counts
+        .iter()
+        .try_fold(0usize, |sum, &c| sum.checked_add(c))
+        .is_none()
+    {
+        return Err("Bucket counts overflow");
+    }
+    let mut spread = vec![0; end as usize + 1];
+    let mut low = 0;
+    for (&high, &count) in boundaries.iter().zip(counts) {
+        let (count, width) = (count as u128, (high - low + 1) as u128);
+        for (offset, slot) in spread[low as usize..=high as usize].iter_mut().enumerate() {
+            let offset = offset as u128;
+            // Each slot is at most `count`, so it fits back in a usize
+            *slot = (count * (offset + 1) / width - count * offset / width) as usize;
+        }
+        low = high + 1;
+    }
+    Ok(spread)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_from_buckets() {
+        // le="10", le="50", le="100" with 10, 80 and 10 observations
+        let estimator = QuantileEstimator::from_buckets(&[10, 50, 100], &[10, 80, 10]).unwrap();
+        assert_eq!(estimator.val_count, 100);
+        assert_eq!(estimator.rank(10), 10);
+        assert_eq!(estimator.rank(50), 90);
+        assert_eq!(estimator.estimate_quantile(0.1).unwrap(), 10);
+        assert_eq!(estimator.estimate_quantile(0.9).unwrap(), 50);
+        // Halfway through the middle bucket
+        assert_eq!(estimator.estimate_quantile(0.5).unwrap(), 30);
+        assert_eq!(estimator.estimate_quantile(1.0).unwrap(), 100);
+
+        // Fewer values than buckets still add up
+        let sparse = QuantileEstimator::from_buckets(&[1000], &[3]).unwrap();
+        assert_eq!(sparse.val_count, 3);
+
+        assert!(QuantileEstimator::from_buckets(&[10, 5], &[1, 1]).is_err());
+        assert!(QuantileEstimator::from_buckets(&[10], &[1, 1]).is_err());
+        assert!(QuantileEstimator::from_buckets(&[], &[]).is_err());
+
+        assert!(QuantileEstimator::from_buckets(&[1, 3], &[usize::MAX, 3]).is_err());
+        let wide = QuantileEstimator::from_buckets(&[1, 3], &[usize::MAX / 2, 3]).unwrap();
+        assert_eq!(wide.val_count, usize::MAX / 2 + 3);
+        // 10 s in nanoseconds, and the top of the range
+        assert!(QuantileEstimator::from_buckets(&[10_000_000_000], &[1]).is_err());
+        assert!(QuantileEstimator::from_buckets(&[u64::MAX], &[1]).is_err());
+    }
+    #[test]
+    fn test_insert_buckets() {
+        let mut buffer = TimeBasedRingBuffer::new(2, 10, 5, 100);
+        buffer.insert(50, 0).unwrap();
+        // Empty buckets below the buffer's range are fine
+        buffer.insert_buckets(&[4, 10, 50], &[0, 6, 4], 3).unwrap();
+        assert_eq!(buffer.estimate_quantile(0.2).unwrap(), 6);
+        assert_eq!(buffer.estimate_quantile(1.0).unwrap(), 50);
+        assert!(buffer.insert_buckets(&[4, 10], &[1, 1], 3).is_err());
+        assert!(buffer.insert_buckets(&[10, 200], &[1, 1], 3).is_err());
+        buffer.insert_buckets(&[100], &[0], 12).unwrap();
+        // Moved on to a new window without recording anything
+        assert_eq!(buffer.snapshot().windows.len(), 2);
+    }
+}
@@ -0,0 +1,109 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::fraction::IntoFraction;
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// A ring buffer that can be shared between threads, with reads that never wait for
+/// writers.
+///
+/// Inserts go through a mutex. Reads are served from a [`Snapshot`] published whenever
+/// a window is completed, so they follow a bounded-staleness contract: a read observes
+/// every insert made before the last completed rotation, and none made after it, except
+/// after an explicit [`refresh`](Self::refresh), which also publishes the window still
+/// in progress. Staleness is therefore at most one window duration, plus the time since
+/// the last insert if inserts stop arriving.
+#[derive(Debug)]
+pub struct ConcurrentRingBuffer {
+    writer: Mutex<TimeBasedRingBuffer>,
+    published: RwLock<Arc<Snapshot>>,
+}
+
+impl ConcurrentRingBuffer {
+    /// Creates a new ConcurrentRingBuffer. See [`TimeBasedRingBuffer::new`].
+    pub fn new(capacity: usize, duration: u64, start: u64, end: u64) -> Self {
+        let buffer = TimeBasedRingBuffer::new(capacity, duration, start, end);
+        let published = RwLock::new(Arc::new(buffer.snapshot()));
+        ConcurrentRingBuffer {
+            writer: Mutex::new(buffer),
+            published,
+        }
+    }
+
+    /// Inserts a value with a timestamp. If the timestamp completes the current window,
+    /// the windows as they were before this insert are published to readers first.
+    pub fn insert(&self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        let mut buffer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.would_rotate(timestamp) {
+            self.publish(buffer.snapshot());
+        }
+        buffer.insert(value, timestamp)
+    }
+
+    /// Inserts a value into the window currently being filled.
+    pub(crate) fn insert_current(&self, value: u64) -> Result<(), &'static str> {
+        let mut buffer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        let now = buffer
+            .current_window_start()
+            .ok_or("No timestamp recorded yet")?;
+        buffer.insert(value, now)
+    }
+
+    /// Publishes the current state, including the window still in progress, so that
+    /// subsequent reads observe every insert made before this call.
+    pub fn refresh(&self) {
+        let buffer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        self.publish(buffer.snapshot());
+    }
+
+    /// Returns the last published snapshot.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        Arc::clone(&self.published.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Returns the quantile of the last published snapshot.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        self.snapshot().estimate_quantile(fraction)
+    }
+
+    fn publish(&self, snapshot: Snapshot) {
+        *self.published.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_reads_observe_completed_rotations() {
+        let buffer = ConcurrentRingBuffer::new(4, 10, 0, 100);
+        buffer.insert(1, 0).unwrap();
+        buffer.insert(2, 5).unwrap();
+        assert!(buffer.estimate_quantile(0.5).is_err());
+        buffer.insert(50, 10).unwrap();
+        assert_eq!(buffer.snapshot().combined().rank(100), 2);
+        assert_eq!(buffer.estimate_quantile(1.0).unwrap(), 2);
+        buffer.refresh();
+        assert_eq!(buffer.estimate_quantile(1.0).unwrap(), 50);
+    }
+    #[test]
+    fn test_concurrent_inserts() {
+        let buffer = Arc::new(ConcurrentRingBuffer::new(4, 1000, 0, 100));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let buffer = Arc::clone(&buffer);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        buffer.insert(t * 10 + i % 10, 0).unwrap();
+                        let _ = buffer.estimate_quantile(0.5);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        buffer.refresh();
+        assert_eq!(buffer.snapshot().combined().rank(100), 400);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::estimator::QuantileEstimator;
+use crate::provenance::fingerprint;
+use crate::snapshot::Snapshot;
+use crate::validate::ValidationIssue;
+
+/// The buckets of one window that changed since a base snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowDelta {
+    pub start: u64,
+    /// `(bucket index, new count)` for every bucket whose count differs from the base,
+    /// in increasing index order.
+    pub changes: Vec<(usize, usize)>,
+}
+
+/// Raw contents of a delta, as sent between processes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaParts {
+    pub base: u64,
+    pub target: u64,
+    /// Fingerprint of the fields above and of every change, checked on load to catch
+    /// deltas corrupted or truncated in transit.
+    pub checksum: u64,
+    pub windows: Vec<WindowDelta>,
+}
+
+/// The difference between two snapshots of the same series, for pushing frequent
+/// snapshots of a slowly changing distribution without resending every bucket.
+///
+/// Only window contents are carried; annotations, contributors and the audit log are
+/// kept from the base when applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SnapshotDelta {
+    /// Content fingerprint of the snapshot the delta was computed against.
+    pub base: u64,
+    /// Content fingerprint of the snapshot the delta produces.
+    pub target: u64,
+    /// Every window of the target, oldest first. Base windows not listed are dropped.
+    pub windows: Vec<WindowDelta>,
+}
+
+impl SnapshotDelta {
+    /// Returns the number of changed buckets, a measure of the encoded size.
+    pub fn changed_buckets(&self) -> usize {
+        self.windows.iter().map(|w| w.changes.len()).sum()
+    }
+
+    /// Rebuilds a delta received from another process, the inverse of
+    /// [`to_parts`](Self::to_parts). Fails if the checksum doesn't match or the windows
+    /// and changes are not in increasing order. Whether the delta fits the receiver's
+    /// base is checked when it is applied.
+    pub fn from_parts(parts: DeltaParts) -> Result<SnapshotDelta, ValidationIssue> {
+        let delta = SnapshotDelta {
+            base: parts.base,
+            target: parts.target,
+            windows: parts.windows,
+        };
+        let checksum = delta.checksum();
+        if checksum != parts.checksum {
+            return Err(ValidationIssue::ChecksumMismatch {
+                expected: checksum,
+                actual: parts.checksum,
+            });
+        }
+        if let Some(pair) = delta.windows.windows(2).find(|w| w[0].start >= w[1].start) {
+            return Err(ValidationIssue::UnorderedWindow {
+                window_start: pair[1].start,
+            });
+        }
+        let ordered = |window: &&WindowDelta| window.changes.windows(2).all(|c| c[0].0 < c[1].0);
+        if let Some(window) = delta.windows.iter().find(|w| !ordered(w)) {
+            return Err(ValidationIssue::UnorderedChange {
+                window_start: window.start,
+            });
+        }
+        Ok(delta)
+    }
+
+    /// Returns the raw contents of the delta, for sending to another process.
+    pub fn to_parts(&self) -> DeltaParts {
+        DeltaParts {
+            base: self.base,
+            target: self.target,
+            checksum: self.checksum(),
+            windows: self.windows.clone(),
+        }
+    }
+
+    fn checksum(&self) -> u64 {
+        let mut words = vec![self.base, self.target];
+        for window in &self.windows {
+            words.extend([window.start, window.changes.len() as u64]);
+            for &(index, count) in &window.changes {
+                words.extend([index as u64, count as u64]);
+            }
+        }
+        fingerprint(&words)
+    }
+}
+
+impl Snapshot {
+    /// Returns a fingerprint of the configuration and of every window's counts. Two
+    /// snapshots with equal content fingerprints hold the same windows.
+    pub fn content_fingerprint(&self) -> u64 {
+        let mut words = vec![self.start, self.end, self.duration];
+        for (start, window) in &self.windows {
+            words.push(*start);
+            for (index, &count) in window.quantiles.iter().enumerate() {
+                if count > 0 {
+                    words.extend([index as u64, count as u64]);
+                }
+            }
+        }
+        fingerprint(&words)
+    }
+
+    /// Returns the buckets that changed since `base`, typically the last snapshot the
+    /// receiver acknowledged. Both must share the same range and duration.
+    pub fn delta_since(&self, base: &Snapshot) -> Result<SnapshotDelta, &'static str> {
+        if self.config_fingerprint() != base.config_fingerprint() {
+            return Err("Snapshot configurations do not match");
+        }
+        let windows = self
+            .windows
+            .iter()
+            .map(|(start, window)| {
+                let before = base
+                    .windows
+                    .binary_search_by_key(start, |(ts, _)| *ts)
+                    .ok()
+                    .map(|i| &base.windows[i].1.quantiles);
+                let changes = window
+                    .quantiles
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, &count)| before.map_or(0, |b| b[i]) != count)
+                    .map(|(i, &count)| (i, count))
+                    .collect();
+                WindowDelta {
+                    start: *start,
+                    changes,
+                }
+            })
+            .collect();
+        Ok(SnapshotDelta {
+            base: base.content_fingerprint(),
+            target: self.content_fingerprint(),
+            windows,
+        })
+    }
+
+    /// Rebuilds the snapshot a delta was computed from, using this snapshot as its base.
+    ///
+    /// Fails if this snapshot isn't the delta's base, e.g. after a lost or reordered
+    /// push, or if the result doesn't match the delta's target. The sender should then
+    /// resync by pushing a full snapshot and computing later deltas against it.
+    pub fn apply_delta(&self, delta: &SnapshotDelta) -> Result<Snapshot, ValidationIssue> {
+        let base = self.content_fingerprint();
+        if base != delta.base {
+            return Err(ValidationIssue::BaseMismatch {
+                expected: delta.base,
+                actual: base,
+            });
+        }
+        let len = (self.end - self.start + 1) as usize;
+        let mut windows = Vec::with_capacity(delta.windows.len());
+        for window in &delta.windows {
+            let mut counts = match self
+                .windows
+                .binary_search_by_key(&window.start, |(ts, _)| *ts)
+            {
+                Ok(i) => self.windows[i].1.quantiles.clone(),
+                Err(_) => vec![0; len],
+            };
+            for &(index, count) in &window.changes {
+                *counts
+                    .get_mut(index)
+                    .ok_or(ValidationIssue::ChangeOutOfRange {
+                        window_start: window.start,
+                        index,
+                    })? = count;
+            }
+            let estimator = QuantileEstimator::from_counts(self.start, self.end, counts);
+            windows.push((window.start, estimator));
+        }
+        let applied = Snapshot {
+            windows,
+            ..self.clone()
+        };
+        let target = applied.content_fingerprint();
+        if target != delta.target {
+            return Err(ValidationIssue::TargetMismatch {
+                expected: delta.target,
+                actual: target,
+            });
+        }
+        Ok(applied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring_buffer::TimeBasedRingBuffer;
+    #[test]
+    fn test_delta_round_trip() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+        for ts in 0..25 {
+            ring_buffer.insert(100 + ts % 5, ts).unwrap();
+        }
+        let acknowledged = ring_buffer.snapshot();
+        for ts in 25..45 {
+            ring_buffer.insert(100 + ts % 3, ts).unwrap();
+        }
+        let current = ring_buffer.snapshot();
+        let delta: SnapshotDelta = current.delta_since(&acknowledged).unwrap();
+        assert!(delta.changed_buckets() < 10);
+        let applied = acknowledged.apply_delta(&delta).unwrap();
+        assert_eq!(applied.content_fingerprint(), current.content_fingerprint());
+        assert_eq!(
+            applied.estimate_quantile(0.9).unwrap(),
+            current.estimate_quantile(0.9).unwrap()
+        );
+
+        // A steady state sends nothing, and a stale base asks for a resync
+        assert_eq!(current.delta_since(&current).unwrap().changed_buckets(), 0);
+        assert!(matches!(
+            current.apply_delta(&delta),
+            Err(ValidationIssue::BaseMismatch { .. })
+        ));
+        let other = TimeBasedRingBuffer::new(3, 5, 0, 1000).snapshot();
+        assert!(current.delta_since(&other).is_err());
+    }
+    #[test]
+    fn test_delta_parts() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+        ring_buffer.insert(100, 0).unwrap();
+        let acknowledged = ring_buffer.snapshot();
+        ring_buffer.insert(200, 15).unwrap();
+        ring_buffer.insert(150, 16).unwrap();
+        let delta = ring_buffer.snapshot().delta_since(&acknowledged).unwrap();
+
+        // What a collector in another process receives
+        let received = SnapshotDelta::from_parts(delta.to_parts()).unwrap();
+        assert_eq!(received, delta);
+        let applied = acknowledged.apply_delta(&received).unwrap();
+        assert_eq!(applied.estimate_quantile(1.0).unwrap(), 200);
+
+        let mut corrupt = delta.to_parts();
+        corrupt.windows[1].changes[0].1 += 1;
+        assert!(matches!(
+            SnapshotDelta::from_parts(corrupt),
+            Err(ValidationIssue::ChecksumMismatch { .. })
+        ));
+        let mut truncated = delta.to_parts();
+        truncated.windows.pop();
+        assert!(SnapshotDelta::from_parts(truncated).is_err());
+        let mut reordered = delta.to_parts();
+        reordered.windows[1].changes.reverse();
+        reordered.checksum = SnapshotDelta {
+            windows: reordered.windows.clone(),
+            ..delta.clone()
+        }
+        .checksum();
+        assert_eq!(
+            SnapshotDelta::from_parts(reordered),
+            Err(ValidationIssue::UnorderedChange { window_start: 10 })
+        );
+
+        let mut outside = delta.clone();
+        outside.windows[1].changes[0].0 = 5000;
+        assert_eq!(
+            acknowledged.apply_delta(&outside).unwrap_err(),
+            ValidationIssue::ChangeOutOfRange {
+                window_start: 10,
+                index: 5000
+            }
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use crate::estimator::QuantileEstimator;
+
+/// Largest number of buckets the domain constructors allocate, 128 MiB of counts on
+/// 64-bit targets. Anything wider should be recorded in coarser units.
+pub(crate) const MAX_BUCKETS: u64 = 1 << 24;
+
+impl QuantileEstimator {
+    /// Creates an estimator for sizes in bytes up to `max`, such as request or payload
+    /// sizes. Values are recorded in bytes.
+    pub fn for_bytes(max: u64) -> Result<Self, &'static str> {
+        if max >= MAX_BUCKETS {
+            return Err("Byte range too wide for unit buckets, record in KiB instead");
+        }
+        Ok(QuantileEstimator::new(0, max))
+    }
+
+    /// Creates an estimator for counts up to `max`, such as queue depths or batch sizes.
+    pub fn for_counts(max: u64) -> Result<Self, &'static str> {
+        if max >= MAX_BUCKETS {
+            return Err("Count range too wide for unit buckets");
+        }
+        Ok(QuantileEstimator::new(0, max))
+    }
+
+    /// Creates an estimator for latencies up to `max`, with one bucket per `resolution`.
+    /// Record values in units of `resolution`, e.g. milliseconds for a 1 ms resolution,
+    /// or convert with [`duration_in`](Self::duration_in).
+    pub fn for_latency(max: Duration, resolution: Duration) -> Result<Self, &'static str> {
+        if resolution.is_zero() {
+            return Err("Resolution must be greater than zero");
+        }
+        let buckets = max.as_nanos() / resolution.as_nanos();
+        if buckets >= MAX_BUCKETS as u128 {
+            return Err("Latency range too wide for the resolution");
+        }
+        Ok(QuantileEstimator::new(0, buckets as u64))
+    }
+
+    /// Converts `elapsed` to whole units of `resolution`, rounding down, for recording
+    /// into an estimator created with [`for_latency`](Self::for_latency).
+    pub fn duration_in(elapsed: Duration, resolution: Duration) -> u64 {
+        let units = elapsed.as_nanos() / resolution.as_nanos().max(1);
+        u64::try_from(units).unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_domain_constructors() {
+        let sizes = QuantileEstimator::for_bytes(64 * 1024).unwrap();
+        assert_eq!((sizes.start, sizes.end), (0, 64 * 1024));
+        assert!(QuantileEstimator::for_bytes(1 << 30).is_err());
+        assert_eq!(QuantileEstimator::for_counts(1000).unwrap().end, 1000);
+
+        let ms = Duration::from_millis(1);
+        let mut latency = QuantileEstimator::for_latency(Duration::from_secs(10), ms).unwrap();
+        assert_eq!(latency.end, 10_000);
+        let elapsed = Duration::from_micros(2_750);
+        latency
+            .add_value(QuantileEstimator::duration_in(elapsed, ms))
+            .unwrap();
+        assert_eq!(latency.estimate_quantile(0.5).unwrap(), 2);
+        assert!(QuantileEstimator::for_latency(Duration::from_secs(60), Duration::ZERO).is_err());
+        let ns = Duration::from_nanos(1);
+        assert!(QuantileEstimator::for_latency(Duration::from_secs(60), ns).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::estimator::{QuantileEstimator, rank_index};
+use crate::fraction::IntoFraction;
+
+/// Records every value at two resolutions: exactly over a narrow fine range, and in
+/// buckets of `coarse_unit` over a wide coarse range, e.g. 0–1000 ms at 1 ms alongside
+/// 0–600 s at 1 s.
+///
+/// A single unit-width range wide enough for the slow tail would cost one bucket per
+/// unit; here the fine range answers exactly whenever the requested rank falls inside
+/// it, and the coarse range answers the rest to within one `coarse_unit`.
+#[derive(Debug, Clone)]
+pub struct DualResolutionEstimator {
+    fine: QuantileEstimator,
+    coarse: QuantileEstimator,
+    coarse_unit: u64,
+}
+
+impl DualResolutionEstimator {
+    /// Creates an estimator recording values in `[0, fine_end]` exactly and values in
+    /// `[0, coarse_end]` in buckets of `coarse_unit`.
+    pub fn new(fine_end: u64, coarse_unit: u64, coarse_end: u64) -> Result<Self, &'static str> {
+        if coarse_unit == 0 {
+            return Err("Coarse unit must be greater than zero");
+        }
+        if coarse_end < fine_end {
+            return Err("Coarse range must cover the fine range");
+        }
+        Ok(DualResolutionEstimator {
+            fine: QuantileEstimator::new(0, fine_end),
+            coarse: QuantileEstimator::new(0, coarse_end / coarse_unit),
+            coarse_unit,
+        })
+    }
+
+    /// Adds a value to the coarse range, and to the fine range if it fits there.
+    pub fn add_value(&mut self, value: u64) -> Result<(), &'static str> {
+        self.coarse.add_value(value / self.coarse_unit)?;
+        if value <= self.fine.end {
+            self.fine.add_value(value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of values added.
+    pub fn val_count(&self) -> usize {
+        self.coarse.val_count
+    }
+
+    /// Returns the estimated quantile. Ranks held by the fine range are answered
+    /// exactly; the others return the lower edge of their coarse bucket.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        if self.coarse.val_count == 0 {
+            return Err("No values added to the estimator");
+        }
+        let index = rank_index(fraction, self.coarse.val_count);
+        // The fine range holds exactly the smallest values, so it agrees on their ranks.
+        let value = if index < self.fine.val_count {
+            self.fine.nth(index)
+        } else {
+            self.coarse
+                .nth(index)
+                .map(|bucket| bucket * self.coarse_unit)
+        };
+        value.ok_or("No quantile found for the given fraction")
+    }
+
+    /// Returns the width of the bucket that answers `fraction`: 1 from the fine range, or
+    /// `coarse_unit` from the coarse range.
+    pub fn resolution(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        let index = rank_index(fraction, self.coarse.val_count);
+        Ok(if index < self.fine.val_count {
+            1
+        } else {
+            self.coarse_unit
+        })
+    }
+
+    /// Returns the exact narrow-range estimator.
+    pub fn fine(&self) -> &QuantileEstimator {
+        &self.fine
+    }
+
+    /// Returns the wide-range estimator, whose values are in units of `coarse_unit`.
+    pub fn coarse(&self) -> &QuantileEstimator {
+        &self.coarse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_dual_resolution() {
+        // 0-1 s at 1 ms alongside 0-10 min at 1 s
+        let mut estimator = DualResolutionEstimator::new(1_000, 1_000, 600_000).unwrap();
+        for ms in 1..=98 {
+            estimator.add_value(ms).unwrap();
+        }
+        estimator.add_value(42_700).unwrap();
+        estimator.add_value(599_999).unwrap();
+        assert!(estimator.add_value(600_001 + 999).is_err());
+        assert_eq!(estimator.val_count(), 100);
+        assert_eq!(estimator.fine().val_count, 98);
+        assert_eq!(estimator.estimate_quantile(0.5).unwrap(), 50);
+        assert_eq!(estimator.estimate_quantile(0.98).unwrap(), 98);
+        assert_eq!(estimator.resolution(0.98).unwrap(), 1);
+        assert_eq!(estimator.estimate_quantile(0.99).unwrap(), 42_000);
+        assert_eq!(estimator.resolution(0.99).unwrap(), 1_000);
+        assert_eq!(estimator.estimate_quantile(1.0).unwrap(), 599_000);
+        assert!(estimator.estimate_quantile(1.5).is_err());
+        assert!(DualResolutionEstimator::new(1_000, 0, 600_000).is_err());
+        assert!(DualResolutionEstimator::new(1_000, 1, 10).is_err());
+    }
+}
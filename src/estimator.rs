@@ -0,0 +1,601 @@
+use crate::fraction::{IntoFraction, checked};
+
+/// Once more than 1/DENSE_RATIO of the buckets are in use, the estimator stops tracking
+/// touched indices, and a reset swaps in a spare set of buckets instead of clearing them.
+const DENSE_RATIO: usize = 4;
+
+/// Spare buckets zeroed per count added. A window needs more than len / DENSE_RATIO adds
+/// to turn dense again, so the spare it retired is clean by its next reset.
+const CLEAN_STEP: usize = DENSE_RATIO;
+
+/// Number of buckets summarized by each entry of `block_counts`.
+pub(crate) const RANK_BLOCK: usize = 1024;
+
+/// Estimates quantiles over a data stream.
+///
+/// Every value in the range has its own bucket, so estimates are exact: a quantile query
+/// returns the added value at index `round(fraction * count) - 1` in sorted order.
+#[derive(Debug, Clone)]
+pub struct QuantileEstimator {
+    pub(crate) val_count: usize,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) quantiles: Vec<usize>,
+    /// Per-block totals of `quantiles`, so rank queries skip whole blocks at a time.
+    pub(crate) block_counts: Vec<usize>,
+    distinct: usize,
+    /// Smallest and largest value added, meaningful only while `val_count > 0`.
+    pub(crate) min: u64,
+    pub(crate) max: u64,
+    touched: Vec<usize>,
+    dense: bool,
+    spare: Spare,
+    /// Inclusive value ranges whose counts were spread evenly from a coarser histogram,
+    /// sorted and disjoint. A value counted in one is only known to lie somewhere in it.
+    pub(crate) spread: Vec<(u64, u64)>,
+    /// Largest relative error of any count, from rounding counts for a compact encoding.
+    pub(crate) count_error: f64,
+}
+
+/// Buckets retired by the last dense reset, zeroed a few at a time as counts are added.
+#[derive(Debug, Default)]
+struct Spare {
+    quantiles: Vec<usize>,
+    block_counts: Vec<usize>,
+    /// Number of leading buckets already zeroed.
+    clean: usize,
+}
+
+/// Copies start without a spare, so snapshots don't carry stale buckets.
+impl Clone for Spare {
+    fn clone(&self) -> Self {
+        Spare::default()
+    }
+}
+
+impl QuantileEstimator {
+    /// Creates a new QuantileEstimator with the given start and end (inclusive).
+    pub fn new(start: u64, end: u64) -> Self {
+        let len = (end - start + 1) as usize;
+        QuantileEstimator {
+            val_count: 0,
+            start,
+            end,
+            quantiles: vec![0; len],
+            block_counts: vec![0; len.div_ceil(RANK_BLOCK)],
+            distinct: 0,
+            min: start,
+            max: start,
+            touched: Vec::new(),
+            dense: false,
+            spare: Spare::default(),
+            spread: Vec::new(),
+            count_error: 0.0,
+        }
+    }
+
+    /// Adds a value to the estimator. Returns error if value is out of range.
+    pub fn add_value(&mut self, value: u64) -> Result<(), &'static str> {
+        if value < self.start || value > self.end {
+            return Err("Value out of range");
+        }
+        self.add_count((value - self.start) as usize, 1);
+        Ok(())
+    }
+
+    /// Adds all counts from `other` into this estimator. Both must cover the same range.
+    pub fn merge(&mut self, other: &QuantileEstimator) -> Result<(), &'static str> {
+        if self.start != other.start || self.end != other.end {
+            return Err("Estimator ranges do not match");
+        }
+        for (index, &count) in other.quantiles.iter().enumerate() {
+            if count > 0 {
+                self.add_count(index, count);
+            }
+        }
+        self.inherit_error(other);
+        Ok(())
+    }
+
+    /// Takes on the spread ranges and count error of `other`, whose counts this estimator
+    /// holds some of.
+    pub(crate) fn inherit_error(&mut self, other: &QuantileEstimator) {
+        self.add_spread(&other.spread);
+        self.count_error = self.count_error.max(other.count_error);
+    }
+
+    /// Marks the values in `ranges` as spread from coarser buckets. Overlapping ranges
+    /// are joined, since a value in the overlap could belong to either.
+    pub(crate) fn add_spread(&mut self, ranges: &[(u64, u64)]) {
+        if ranges.is_empty() {
+            return;
+        }
+        let mut all: Vec<(u64, u64)> = self.spread.iter().chain(ranges).copied().collect();
+        all.sort_unstable();
+        let mut joined: Vec<(u64, u64)> = Vec::with_capacity(all.len());
+        for (low, high) in all {
+            match joined.last_mut() {
+                Some(last) if low <= last.1 => last.1 = last.1.max(high),
+                _ => joined.push((low, high)),
+            }
+        }
+        self.spread = joined;
+    }
+
+    /// Returns the spread range holding `value`, or `value` alone if it was counted
+    /// exactly.
+    pub(crate) fn spread_around(&self, value: u64) -> (u64, u64) {
+        let next = self.spread.partition_point(|&(low, _)| low <= value);
+        match next.checked_sub(1).map(|i| self.spread[i]) {
+            Some((low, high)) if value <= high => (low, high),
+            _ => (value, value),
+        }
+    }
+
+    /// Builds an estimator over `start..=end` from already combined bucket counts.
+    pub(crate) fn from_counts(start: u64, end: u64, counts: Vec<usize>) -> Self {
+        let block_counts = counts
+            .chunks(RANK_BLOCK)
+            .map(|block| block.iter().sum())
+            .collect();
+        let mut estimator = QuantileEstimator {
+            val_count: counts.iter().sum(),
+            start,
+            end,
+            quantiles: counts,
+            block_counts,
+            distinct: 0,
+            min: start,
+            max: start,
+            touched: Vec::new(),
+            dense: true,
+            spare: Spare::default(),
+            spread: Vec::new(),
+            count_error: 0.0,
+        };
+        estimator.distinct = estimator.quantiles.iter().filter(|&&c| c > 0).count();
+        let quantiles = &estimator.quantiles;
+        if let Some(first) = quantiles.iter().position(|&c| c > 0) {
+            let last = quantiles.iter().rposition(|&c| c > 0).unwrap_or(first);
+            estimator.min = start + first as u64;
+            estimator.max = start + last as u64;
+        }
+        if estimator.distinct <= estimator.quantiles.len() / DENSE_RATIO {
+            estimator.touched = (0..estimator.quantiles.len())
+                .filter(|&i| estimator.quantiles[i] > 0)
+                .collect();
+            estimator.dense = false;
+        }
+        estimator
+    }
+
+    /// Returns a copy with every count multiplied by `numerator / denominator`, rounded
+    /// to the nearest integer.
+    pub(crate) fn scaled(&self, numerator: u64, denominator: u64) -> Self {
+        let counts = self
+            .quantiles
+            .iter()
+            .map(|&c| {
+                let scaled = 2 * c as u128 * numerator as u128 + denominator as u128;
+                (scaled / (2 * denominator as u128)) as usize
+            })
+            .collect();
+        let mut scaled = QuantileEstimator::from_counts(self.start, self.end, counts);
+        scaled.inherit_error(self);
+        scaled
+    }
+
+    fn add_count(&mut self, index: usize, count: usize) {
+        self.clean_spare(CLEAN_STEP);
+        if self.quantiles[index] == 0 {
+            self.distinct += 1;
+        }
+        if self.quantiles[index] == 0 && !self.dense {
+            if self.touched.len() < self.quantiles.len() / DENSE_RATIO {
+                self.touched.push(index);
+            } else {
+                self.touched.clear();
+                self.dense = true;
+            }
+        }
+        let value = self.start + index as u64;
+        if self.val_count == 0 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.val_count += count;
+        self.quantiles[index] += count;
+        self.block_counts[index / RANK_BLOCK] += count;
+    }
+
+    /// Returns the estimated quantile for a given fraction.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        if self.val_count == 0 {
+            return Err("No values added to the estimator");
+        }
+        let index = rank_index(fraction, self.val_count);
+        if let Some(extreme) = self.extreme(index) {
+            return Ok(extreme);
+        }
+        let mut cumulative = 0;
+        for (i, &count) in self.quantiles.iter().enumerate() {
+            cumulative += count;
+            if cumulative > index {
+                return Ok(self.start + i as u64);
+            }
+        }
+        Err("No quantile found for the given fraction")
+    }
+
+    /// Returns the smallest value added, or `None` if the estimator is empty.
+    pub fn min(&self) -> Option<u64> {
+        (self.val_count > 0).then_some(self.min)
+    }
+
+    /// Returns the largest value added, or `None` if the estimator is empty.
+    pub fn max(&self) -> Option<u64> {
+        (self.val_count > 0).then_some(self.max)
+    }
+
+    /// Answers rank `index` without scanning when it is the first or last value.
+    fn extreme(&self, index: usize) -> Option<u64> {
+        match index {
+            _ if self.val_count == 0 => None,
+            0 => Some(self.min),
+            i if i + 1 == self.val_count => Some(self.max),
+            _ => None,
+        }
+    }
+
+    /// Returns the estimated quantiles for several fractions with a single scan over the
+    /// buckets. Results are in the same order as `fractions`.
+    pub fn estimate_quantiles(
+        &self,
+        fractions: &[impl IntoFraction],
+    ) -> Result<Vec<u64>, &'static str> {
+        let fractions = checked(fractions)?;
+        if self.val_count == 0 {
+            return Err("No values added to the estimator");
+        }
+        let mut results = vec![0; fractions.len()];
+        let mut order: Vec<(usize, usize)> = Vec::with_capacity(fractions.len());
+        for (slot, &f) in fractions.iter().enumerate() {
+            let index = rank_index(f, self.val_count);
+            match self.extreme(index) {
+                Some(extreme) => results[slot] = extreme,
+                None => order.push((index, slot)),
+            }
+        }
+        if order.is_empty() {
+            return Ok(results);
+        }
+        order.sort_unstable();
+        let mut pending = order.iter().peekable();
+        let mut cumulative = 0;
+        for (i, &count) in self.quantiles.iter().enumerate() {
+            cumulative += count;
+            while let Some(&&(index, slot)) = pending.peek() {
+                if cumulative <= index {
+                    break;
+                }
+                results[slot] = self.start + i as u64;
+                pending.next();
+            }
+            if pending.peek().is_none() {
+                return Ok(results);
+            }
+        }
+        Err("No quantile found for the given fraction")
+    }
+
+    /// Returns the number of added values less than or equal to `value`.
+    pub fn rank(&self, value: u64) -> usize {
+        if value < self.start {
+            return 0;
+        }
+        if value >= self.end {
+            return self.val_count;
+        }
+        let index = (value - self.start) as usize;
+        let block = index / RANK_BLOCK;
+        let whole: usize = self.block_counts[..block].iter().sum();
+        let partial: usize = self.quantiles[block * RANK_BLOCK..=index].iter().sum();
+        whole + partial
+    }
+
+    /// Returns the value at `index` in sorted order, skipping whole blocks before it.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub(crate) fn nth(&self, index: usize) -> Option<u64> {
+        if let Some(extreme) = self.extreme(index) {
+            return Some(extreme);
+        }
+        let mut cumulative = 0;
+        for (block, &block_total) in self.block_counts.iter().enumerate() {
+            if cumulative + block_total <= index {
+                cumulative += block_total;
+                continue;
+            }
+            let block_start = block * RANK_BLOCK;
+            let block_end = (block_start + RANK_BLOCK).min(self.quantiles.len());
+            for i in block_start..block_end {
+                cumulative += self.quantiles[i];
+                if cumulative > index {
+                    return Some(self.start + i as u64);
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the number of distinct values added. Buckets have unit width, so this is
+    /// exact and kept up to date on insert rather than approximated with a sketch.
+    pub fn distinct_estimate(&self) -> usize {
+        self.distinct
+    }
+
+    /// Returns the bytes held by the estimator, including its bucket allocations. A dense
+    /// window is charged for its spare buckets as soon as it turns dense, before the reset
+    /// that allocates them, so a memory cap sees the doubling coming.
+    pub(crate) fn memory_usage(&self) -> usize {
+        let spare = if self.dense && self.spare.quantiles.is_empty() {
+            self.quantiles.len() + self.block_counts.len()
+        } else {
+            self.spare.quantiles.capacity() + self.spare.block_counts.capacity()
+        };
+        let buckets = self.quantiles.capacity() + self.block_counts.capacity() + spare;
+        size_of::<Self>()
+            + (buckets + self.touched.capacity()) * size_of::<usize>()
+            + self.spread.capacity() * size_of::<(u64, u64)>()
+    }
+
+    /// Zeroes all counts, keeping the bucket allocation for reuse.
+    ///
+    /// A sparse window clears only the buckets written since the last reset. A dense one
+    /// swaps in its spare buckets, already zeroed by the inserts since, and retires its
+    /// own to be zeroed by the inserts to come. Neither touches the whole range, so the
+    /// cost of a rotation doesn't grow with it.
+    pub(crate) fn reset(&mut self) {
+        if self.dense {
+            let len = self.quantiles.len();
+            if self.spare.quantiles.len() != len {
+                // The first dense reset allocates zeroed memory rather than filling it
+                self.spare = Spare {
+                    quantiles: vec![0; len],
+                    block_counts: vec![0; self.block_counts.len()],
+                    clean: len,
+                };
+            }
+            // Never needed given CLEAN_STEP, but keeps the counts right regardless
+            self.clean_spare(len);
+            std::mem::swap(&mut self.quantiles, &mut self.spare.quantiles);
+            std::mem::swap(&mut self.block_counts, &mut self.spare.block_counts);
+            self.spare.clean = 0;
+            self.dense = false;
+        } else {
+            for &index in &self.touched {
+                self.quantiles[index] = 0;
+                self.block_counts[index / RANK_BLOCK] = 0;
+            }
+        }
+        self.touched.clear();
+        self.val_count = 0;
+        self.distinct = 0;
+        self.spread.clear();
+        self.count_error = 0.0;
+    }
+
+    /// Zeroes up to `step` more buckets of the spare.
+    fn clean_spare(&mut self, step: usize) {
+        let spare = &mut self.spare;
+        let end = spare.clean.saturating_add(step).min(spare.quantiles.len());
+        for index in spare.clean..end {
+            spare.quantiles[index] = 0;
+            if index.is_multiple_of(RANK_BLOCK) {
+                spare.block_counts[index / RANK_BLOCK] = 0;
+            }
+        }
+        spare.clean = end;
+    }
+}
+
+/// Returns the zero-based position of the value answering `fraction` among `count` values,
+/// i.e. `round(fraction * count) - 1`, clamped at zero.
+///
+/// `fraction` must be in `0.0..=1.0`. It is decomposed into its exact binary value
+/// `mantissa / 2^shift`, so the product and rounding use integer arithmetic only and give
+/// the same result on every platform.
+pub(crate) fn rank_index(fraction: f64, count: usize) -> usize {
+    let bits = fraction.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as u32;
+    let fraction_bits = (bits & ((1 << 52) - 1)) as u128;
+    let (mantissa, shift) = if exponent == 0 {
+        (fraction_bits, 1074)
+    } else {
+        (fraction_bits | 1 << 52, 1075 - exponent)
+    };
+    // mantissa < 2^53 and count < 2^64, so the product always fits in 117 bits.
+    let product = mantissa * count as u128;
+    let rounded = if shift > 117 {
+        0
+    } else {
+        (product + (1 << (shift - 1))) >> shift
+    };
+    (rounded as usize).saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_quantile_estimator() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        for i in 1..=100 {
+            estimator.add_value(i).unwrap();
+        }
+        assert_eq!(estimator.estimate_quantile(0.5).unwrap(), 50);
+        assert_eq!(estimator.estimate_quantile(0.9).unwrap(), 90);
+        assert_eq!(estimator.estimate_quantile(0.99).unwrap(), 99);
+        assert_eq!(estimator.estimate_quantile(0.0).unwrap(), 1);
+        assert_eq!(estimator.estimate_quantile(1.0).unwrap(), 100);
+        assert!(estimator.estimate_quantile(1.1).is_err());
+        let empty_estimator = QuantileEstimator::new(0, 100);
+        assert!(empty_estimator.estimate_quantile(0.5).is_err());
+    }
+    #[test]
+    fn test_reset_clears_sparse_and_dense_windows() {
+        let mut sparse = QuantileEstimator::new(0, 99);
+        sparse.add_value(3).unwrap();
+        sparse.add_value(3).unwrap();
+        sparse.add_value(90).unwrap();
+        assert_eq!(sparse.touched, vec![3, 90]);
+        sparse.reset();
+        assert!(sparse.quantiles.iter().all(|&c| c == 0));
+        assert!(sparse.touched.is_empty());
+
+        let mut dense = QuantileEstimator::new(0, 99);
+        let sparse_usage = dense.memory_usage();
+        for i in 0..50 {
+            dense.add_value(i).unwrap();
+        }
+        assert!(dense.dense);
+        // The spare is charged from the moment the window turns dense, and allocating it
+        // at the reset doesn't change the total
+        let dense_usage = dense.memory_usage();
+        assert!(dense_usage >= sparse_usage + (100 + 1) * size_of::<usize>());
+        dense.reset();
+        assert_eq!(dense.memory_usage(), dense_usage);
+        assert!(!dense.dense);
+        assert!(dense.quantiles.iter().all(|&c| c == 0));
+        assert!(dense.estimate_quantile(0.5).is_err());
+
+        // Later dense resets swap back the retired buckets, zeroed by the inserts since
+        let mut wide = QuantileEstimator::new(0, 4 * RANK_BLOCK as u64);
+        let mut buffers = Vec::new();
+        for round in 0..4u64 {
+            for i in 0..=wide.end {
+                wide.add_value((i + round) % (wide.end + 1)).unwrap();
+            }
+            wide.add_value(round).unwrap();
+            assert_eq!(wide.estimate_quantile(0.0).unwrap(), 0);
+            assert_eq!(wide.rank(round), round as usize + 2);
+            buffers.push(wide.quantiles.as_ptr());
+            if round > 0 {
+                assert_eq!(wide.spare.clean, wide.quantiles.len());
+            }
+            wide.reset();
+            assert!(wide.quantiles.iter().all(|&c| c == 0));
+            assert!(wide.block_counts.iter().all(|&c| c == 0));
+        }
+        assert_eq!(buffers[0], buffers[2]);
+        assert_eq!(buffers[1], buffers[3]);
+        assert!(wide.clone().spare.quantiles.is_empty());
+    }
+    #[test]
+    fn test_estimate_quantiles() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        for i in 1..=100 {
+            estimator.add_value(i).unwrap();
+        }
+        assert_eq!(
+            estimator
+                .estimate_quantiles(&[0.99, 0.5, 0.0, 0.5])
+                .unwrap(),
+            vec![99, 50, 1, 50]
+        );
+        assert!(estimator.estimate_quantiles(&[0.5, -0.1]).is_err());
+        assert!(
+            QuantileEstimator::new(0, 1)
+                .estimate_quantiles(&[0.5])
+                .is_err()
+        );
+    }
+    #[test]
+    fn test_rank_index_matches_rounding() {
+        assert_eq!(rank_index(0.0, 100), 0);
+        assert_eq!(rank_index(1.0, 100), 99);
+        assert_eq!(rank_index(0.5, 0), 0);
+        assert_eq!(rank_index(f64::MIN_POSITIVE, usize::MAX), 0);
+        assert_eq!(rank_index(1.0, usize::MAX), usize::MAX - 1);
+        // Ties round up, as f64::round does for positive values
+        assert_eq!(rank_index(0.5, 3), 1);
+        assert_eq!(rank_index(0.25, 2), 0);
+        for count in [1, 2, 7, 100, 1000, 123_457] {
+            for fraction in [0.001, 0.1, 0.25, 0.333, 0.5, 0.9, 0.99, 0.999] {
+                let float = ((fraction * count as f64 - 1.0).round() as isize).max(0) as usize;
+                assert_eq!(rank_index(fraction, count), float, "{fraction} of {count}");
+            }
+        }
+    }
+    #[test]
+    fn test_merge() {
+        let mut a = QuantileEstimator::new(0, 100);
+        let mut b = QuantileEstimator::new(0, 100);
+        for i in 1..=50 {
+            a.add_value(i).unwrap();
+            b.add_value(i + 50).unwrap();
+        }
+        a.merge(&b).unwrap();
+        assert_eq!(a.val_count, 100);
+        assert_eq!(a.estimate_quantile(0.5).unwrap(), 50);
+        assert_eq!(a.estimate_quantile(1.0).unwrap(), 100);
+        assert!(a.dense);
+        assert!(a.merge(&QuantileEstimator::new(0, 10)).is_err());
+    }
+    #[test]
+    fn test_rank() {
+        let mut estimator = QuantileEstimator::new(10, 5000);
+        for v in [10, 20, 20, 1500, 3000, 5000] {
+            estimator.add_value(v).unwrap();
+        }
+        assert_eq!(estimator.rank(0), 0);
+        assert_eq!(estimator.rank(10), 1);
+        assert_eq!(estimator.rank(20), 3);
+        assert_eq!(estimator.rank(1499), 3);
+        assert_eq!(estimator.rank(1500), 4);
+        assert_eq!(estimator.rank(4999), 5);
+        assert_eq!(estimator.rank(u64::MAX), 6);
+        estimator.reset();
+        assert!(estimator.block_counts.iter().all(|&c| c == 0));
+    }
+    #[test]
+    fn test_distinct_estimate() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        for v in [5, 5, 5, 30, 70] {
+            estimator.add_value(v).unwrap();
+        }
+        assert_eq!(estimator.distinct_estimate(), 3);
+        let mut other = QuantileEstimator::new(0, 100);
+        other.add_value(5).unwrap();
+        other.add_value(6).unwrap();
+        estimator.merge(&other).unwrap();
+        assert_eq!(estimator.distinct_estimate(), 4);
+        estimator.reset();
+        assert_eq!(estimator.distinct_estimate(), 0);
+    }
+    #[test]
+    fn test_min_max() {
+        let mut estimator = QuantileEstimator::new(0, 5000);
+        assert_eq!((estimator.min(), estimator.max()), (None, None));
+        for v in [40, 7, 4100, 900] {
+            estimator.add_value(v).unwrap();
+        }
+        assert_eq!((estimator.min(), estimator.max()), (Some(7), Some(4100)));
+        assert_eq!(estimator.estimate_quantile(0.0).unwrap(), 7);
+        assert_eq!(estimator.estimate_quantile(1.0).unwrap(), 4100);
+        assert_eq!(estimator.estimate_quantile(0.1).unwrap(), 7);
+        assert_eq!(estimator.estimate_quantile(0.99).unwrap(), 4100);
+        assert_eq!(
+            estimator.estimate_quantiles(&[1.0, 0.5, 0.0]).unwrap(),
+            vec![4100, 40, 7]
+        );
+        let rebuilt = QuantileEstimator::from_counts(0, 5000, estimator.quantiles.clone());
+        assert_eq!((rebuilt.min(), rebuilt.max()), (Some(7), Some(4100)));
+        estimator.reset();
+        assert_eq!(estimator.max(), None);
+        estimator.add_value(3).unwrap();
+        assert_eq!((estimator.min(), estimator.max()), (Some(3), Some(3)));
+    }
+}
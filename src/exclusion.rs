@@ -0,0 +1,221 @@
+use std::ops::{Range, RangeInclusive};
+
+use crate::estimator::{QuantileEstimator, RANK_BLOCK, rank_index};
+use crate::fraction::IntoFraction;
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// Values to leave out of a quantile query, e.g. a fixed timeout value or cache hits
+/// below some threshold. Counts are left untouched; excluded buckets are skipped at query
+/// time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exclusion {
+    ranges: Vec<RangeInclusive<u64>>,
+}
+
+impl Exclusion {
+    /// Creates an exclusion that leaves out nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves out a single value, e.g. `value(30_000)` for a timeout.
+    pub fn value(self, value: u64) -> Self {
+        self.range(value..=value)
+    }
+
+    /// Leaves out every value in `range`, e.g. `range(0..=1)` for cache hits.
+    pub fn range(mut self, range: RangeInclusive<u64>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Returns true if `value` is left out.
+    pub fn contains(&self, value: u64) -> bool {
+        self.ranges.iter().any(|r| r.contains(&value))
+    }
+
+    /// Returns the excluded bucket indices of an estimator over `[start, end]`, sorted and
+    /// without overlaps.
+    fn bucket_ranges(&self, start: u64, end: u64) -> Vec<Range<usize>> {
+        let mut clipped: Vec<Range<usize>> = self
+            .ranges
+            .iter()
+            .filter(|r| !r.is_empty() && *r.start() <= end && *r.end() >= start)
+            .map(|r| {
+                let low = r.start().max(&start) - start;
+                let high = r.end().min(&end) - start;
+                low as usize..high as usize + 1
+            })
+            .collect();
+        clipped.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(clipped.len());
+        for range in clipped {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+impl QuantileEstimator {
+    /// Like [`estimate_quantile`](Self::estimate_quantile), but ranks only the values not
+    /// left out by `exclusion`.
+    pub fn estimate_quantile_excluding(
+        &self,
+        fraction: impl IntoFraction,
+        exclusion: &Exclusion,
+    ) -> Result<u64, &'static str> {
+        select_excluding(&[self], fraction, exclusion)
+    }
+}
+
+impl TimeBasedRingBuffer {
+    /// Returns the quantile of all windows combined, ignoring values left out by
+    /// `exclusion`.
+    pub fn estimate_quantile_excluding(
+        &self,
+        fraction: impl IntoFraction,
+        exclusion: &Exclusion,
+    ) -> Result<u64, &'static str> {
+        let windows: Vec<&QuantileEstimator> = self.windows().map(|(_, w)| w).collect();
+        select_excluding(&windows, fraction, exclusion)
+    }
+}
+
+impl Snapshot {
+    /// Returns the quantile of all windows combined, ignoring values left out by
+    /// `exclusion`.
+    pub fn estimate_quantile_excluding(
+        &self,
+        fraction: impl IntoFraction,
+        exclusion: &Exclusion,
+    ) -> Result<u64, &'static str> {
+        let windows: Vec<&QuantileEstimator> = self.windows.iter().map(|(_, w)| w).collect();
+        select_excluding(&windows, fraction, exclusion)
+    }
+}
+
+/// Same two-level walk as [`select_quantile`](crate::select_quantile), with excluded
+/// buckets subtracted from the block totals and skipped inside the selected block.
+fn select_excluding(
+    estimators: &[&QuantileEstimator],
+    fraction: impl IntoFraction,
+    exclusion: &Exclusion,
+) -> Result<u64, &'static str> {
+    let fraction = fraction.into_fraction()?.get();
+    let first = *estimators.first().ok_or("No estimators to query")?;
+    if estimators
+        .iter()
+        .any(|e| e.start != first.start || e.end != first.end)
+    {
+        return Err("Estimator ranges do not match");
+    }
+    let excluded = exclusion.bucket_ranges(first.start, first.end);
+    let excluded_in = |range: Range<usize>| -> usize {
+        excluded
+            .iter()
+            .map(|r| r.start.max(range.start)..r.end.min(range.end))
+            .filter(|r| !r.is_empty())
+            .map(|r| {
+                estimators
+                    .iter()
+                    .map(|e| e.quantiles[r.clone()].iter().sum::<usize>())
+                    .sum::<usize>()
+            })
+            .sum()
+    };
+    let total: usize = estimators.iter().map(|e| e.val_count).sum::<usize>()
+        - excluded_in(0..first.quantiles.len());
+    if total == 0 {
+        return Err("No values left after exclusion");
+    }
+    let index = rank_index(fraction, total);
+    let mut cumulative = 0;
+    for block in 0..first.block_counts.len() {
+        let block_start = block * RANK_BLOCK;
+        let block_end = (block_start + RANK_BLOCK).min(first.quantiles.len());
+        let block_total = estimators
+            .iter()
+            .map(|e| e.block_counts[block])
+            .sum::<usize>()
+            - excluded_in(block_start..block_end);
+        if cumulative + block_total <= index {
+            cumulative += block_total;
+            continue;
+        }
+        for i in block_start..block_end {
+            if excluded.iter().any(|r| r.contains(&i)) {
+                continue;
+            }
+            cumulative += estimators.iter().map(|e| e.quantiles[i]).sum::<usize>();
+            if cumulative > index {
+                return Ok(first.start + i as u64);
+            }
+        }
+    }
+    Err("No quantile found for the given fraction")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_estimate_quantile_excluding() {
+        let mut estimator = QuantileEstimator::new(0, 40_000);
+        for v in [0, 1, 1, 5, 10, 20, 30_000, 30_000, 30_000, 30_000] {
+            estimator.add_value(v).unwrap();
+        }
+        let exclusion = Exclusion::new().value(30_000).range(0..=1);
+        assert!(exclusion.contains(1) && !exclusion.contains(2));
+        assert_eq!(estimator.estimate_quantile(0.99).unwrap(), 30_000);
+        assert_eq!(
+            estimator
+                .estimate_quantile_excluding(0.99, &exclusion)
+                .unwrap(),
+            20
+        );
+        assert_eq!(
+            estimator
+                .estimate_quantile_excluding(0.0, &exclusion)
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            estimator
+                .estimate_quantile_excluding(0.5, &Exclusion::new())
+                .unwrap(),
+            estimator.estimate_quantile(0.5).unwrap()
+        );
+        // Overlapping and out-of-range exclusions are clipped and merged
+        let everything = Exclusion::new().range(0..=20).range(10..=u64::MAX);
+        assert!(
+            estimator
+                .estimate_quantile_excluding(0.5, &everything)
+                .is_err()
+        );
+    }
+    #[test]
+    fn test_ring_buffer_excluding() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 5000);
+        for (value, ts) in [(4000, 0), (2000, 10), (3000, 10), (4000, 20)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        let exclusion = Exclusion::new().value(4000);
+        assert_eq!(
+            ring_buffer
+                .estimate_quantile_excluding(1.0, &exclusion)
+                .unwrap(),
+            3000
+        );
+        assert_eq!(
+            ring_buffer
+                .snapshot()
+                .estimate_quantile_excluding(0.0, &exclusion)
+                .unwrap(),
+            2000
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::estimator::QuantileEstimator;
+use crate::fraction::{IntoFraction, checked};
+use crate::registry::{QuantileRegistry, matches_pattern};
+use crate::snapshot::Snapshot;
+
+/// Label name that refers to the series name in filters.
+pub const NAME_LABEL: &str = "__name__";
+
+/// Splits a series key of the form `name{label="value",...}` into its name and labels.
+/// Keys without braces have no labels. Quotes around values are optional; quoted values
+/// may contain commas and the Prometheus escapes `\\`, `\"` and `\n`, which are undone.
+pub fn parse_key(key: &str) -> (&str, Vec<(&str, Cow<'_, str>)>) {
+    let Some((name, rest)) = key.split_once('{') else {
+        return (key, Vec::new());
+    };
+    let mut rest = rest.strip_suffix('}').unwrap_or(rest);
+    let mut labels = Vec::new();
+    while let Some((label, after)) = rest.split_once('=') {
+        // Text before a comma is a pair without `=`, which is skipped
+        let label = label.rsplit(',').next().unwrap_or(label).trim();
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = unquote(quoted);
+                (value, next.split_once(',').map_or("", |(_, next)| next))
+            }
+            None => {
+                let (value, next) = after.split_once(',').unwrap_or((after, ""));
+                (Cow::Borrowed(value.trim()), next)
+            }
+        };
+        labels.push((label, value));
+        rest = next;
+    }
+    (name, labels)
+}
+
+/// Reads a quoted label value up to its closing quote, undoing escapes, and returns it
+/// with the text after the quote. An unterminated value runs to the end.
+fn unquote(quoted: &str) -> (Cow<'_, str>, &str) {
+    let mut value = String::new();
+    let mut copied = 0;
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let value = if copied == 0 {
+                    Cow::Borrowed(&quoted[..i])
+                } else {
+                    value.push_str(&quoted[copied..i]);
+                    Cow::Owned(value)
+                };
+                return (value, &quoted[i + 1..]);
+            }
+            '\\' => {
+                value.push_str(&quoted[copied..i]);
+                let (escaped, len) = match chars.next() {
+                    Some((_, 'n')) => ('\n', 2),
+                    Some((_, c)) => (c, 1 + c.len_utf8()),
+                    None => ('\\', 1),
+                };
+                value.push(escaped);
+                copied = i + len;
+            }
+            _ => {}
+        }
+    }
+    if copied == 0 {
+        return (Cow::Borrowed(quoted), "");
+    }
+    value.push_str(&quoted[copied..]);
+    (Cow::Owned(value), "")
+}
+
+/// Selects which series an export includes, by label. Values are matched with `*`
+/// wildcards, and [`NAME_LABEL`] matches the series name.
+///
+/// A series is exported if it matches every include rule and no exclude rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportFilter {
+    include: Vec<(String, String)>,
+    exclude: Vec<(String, String)>,
+}
+
+impl ExportFilter {
+    /// Creates a filter that exports everything.
+    pub fn new() -> Self {
+        ExportFilter::default()
+    }
+
+    /// Only exports series whose `label` matches `pattern`, e.g. `include("tier", "edge")`.
+    pub fn include(mut self, label: &str, pattern: &str) -> Self {
+        self.include.push((label.to_string(), pattern.to_string()));
+        self
+    }
+
+    /// Skips series whose `label` matches `pattern`, e.g. `exclude(NAME_LABEL, "*healthcheck*")`.
+    pub fn exclude(mut self, label: &str, pattern: &str) -> Self {
+        self.exclude.push((label.to_string(), pattern.to_string()));
+        self
+    }
+
+    /// Returns true if the series `key` passes the filter.
+    pub fn matches(&self, key: &str) -> bool {
+        let (name, labels) = parse_key(key);
+        let value_of = |label: &str| {
+            if label == NAME_LABEL {
+                Some(name)
+            } else {
+                labels
+                    .iter()
+                    .find(|(l, _)| *l == label)
+                    .map(|(_, v)| v.as_ref())
+            }
+        };
+        let rule_matches = |(label, pattern): &(String, String)| {
+            value_of(label).is_some_and(|value| matches_pattern(pattern, value))
+        };
+        self.include.iter().all(rule_matches) && !self.exclude.iter().any(rule_matches)
+    }
+}
+
+impl QuantileRegistry {
+    /// Snapshots the series passing `filter`, sorted by key. Excluded series are skipped
+    /// before any copying, so they cost nothing on the export path.
+    pub fn snapshots(&self, filter: &ExportFilter) -> Vec<(String, Snapshot)> {
+        let mut keys: Vec<&str> = self.keys().filter(|key| filter.matches(key)).collect();
+        keys.sort_unstable();
+        keys.into_iter()
+            .filter_map(|key| Some((key.to_string(), self.get(key)?.snapshot())))
+            .collect()
+    }
+
+    /// Renders the series passing `filter` in the Prometheus text format, as summaries
+    /// with one sample per fraction plus a `_count` sample. Series are grouped by metric
+    /// name, so each family is contiguous under a single `# TYPE` line. Fails if a
+    /// fraction is NaN or outside `0.0..=1.0`.
+    pub fn prometheus_summary(
+        &self,
+        fractions: &[impl IntoFraction],
+        filter: &ExportFilter,
+    ) -> Result<String, &'static str> {
+        let fractions = checked(fractions)?;
+        let mut out = String::new();
+        let snapshots = self.snapshots(filter);
+        let mut series: Vec<_> = snapshots
+            .iter()
+            .map(|(key, snapshot)| (parse_key(key), snapshot))
+            .collect();
+        // Keys sort `latency_b` between `latency` and `latency{...}`, so regroup by name
+        series.sort_by_key(|((name, _), _)| *name);
+        let mut last_name = None;
+        for ((name, labels), snapshot) in series {
+            if last_name != Some(name) {
+                let _ = writeln!(out, "# TYPE {name} summary");
+                last_name = Some(name);
+            }
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(l, v)| format!("{l}=\"{}\"", escape_label_value(v)))
+                .collect();
+            let combined = snapshot.combined();
+            // A series whose windows all rotated out has a count but no quantiles
+            if combined.val_count > 0 {
+                let values = combined.estimate_quantiles(&fractions)?;
+                for (fraction, value) in fractions.iter().zip(values) {
+                    let mut with_quantile = labels.clone();
+                    with_quantile.push(format!("quantile=\"{fraction}\""));
+                    let _ = writeln!(out, "{name}{{{}}} {value}", with_quantile.join(","));
+                }
+            }
+            let count_labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", labels.join(","))
+            };
+            let _ = writeln!(out, "{name}_count{count_labels} {}", combined.val_count);
+        }
+        Ok(out)
+    }
+
+    /// Renders the series passing `filter` as JSON Lines, one object per window per
+    /// fraction, each tagged with its series key. Fails if a fraction is NaN or outside
+    /// `0.0..=1.0`.
+    pub fn json_lines(
+        &self,
+        fractions: &[impl IntoFraction],
+        filter: &ExportFilter,
+    ) -> Result<String, &'static str> {
+        let fractions = checked(fractions)?;
+        let mut out = String::new();
+        for (key, snapshot) in self.snapshots(filter) {
+            write_json_lines(&mut out, Some(&key), &snapshot, &fractions)?;
+        }
+        Ok(out)
+    }
+}
+
+impl Snapshot {
+    /// Returns a copy with every bucket count rounded to `digits` significant digits, so
+    /// serialized snapshots compress better. Non-zero counts stay non-zero.
+    ///
+    /// Each count moves by at most ε = ½·10^(1−digits) of itself, so the number of values
+    /// below, and above, any point moves by at most ε too. The estimate for fraction `f`
+    /// is then the true quantile at a fraction whose distance from either end is within a
+    /// factor of (1+ε)/(1−ε) of `f`'s: with 2 digits, p99 lands between the true p98.9
+    /// and p99.1. The copy remembers ε, so [`report`](Snapshot::report) and
+    /// [`table`](Snapshot::table) widen their bounds to match.
+    pub fn quantized(&self, digits: u32) -> Snapshot {
+        let epsilon = match digits {
+            0 => 0.0,
+            _ => 0.5 * 10f64.powi(1 - digits as i32),
+        };
+        let quantize = |window: &QuantileEstimator| {
+            let counts = window
+                .quantiles
+                .iter()
+                .map(|&c| round_significant(c, digits))
+                .collect();
+            let mut quantized = QuantileEstimator::from_counts(window.start, window.end, counts);
+            quantized.inherit_error(window);
+            // Rounding an already rounded count compounds the two errors
+            let error = quantized.count_error;
+            quantized.count_error = error + epsilon + error * epsilon;
+            quantized
+        };
+        Snapshot {
+            windows: self
+                .windows
+                .iter()
+                .map(|(ts, w)| (*ts, quantize(w)))
+                .collect(),
+            contributor_index: self
+                .contributor_index
+                .as_ref()
+                .map(|index| index.iter().map(quantize).collect()),
+            ..self.clone()
+        }
+    }
+
+    /// Renders the snapshot as JSON Lines, one object per non-empty window per fraction:
+    /// `{"start":10,"end":20,"quantile":0.99,"value":42,"count":7}`. Fails if a fraction
+    /// is NaN or outside `0.0..=1.0`.
+    pub fn json_lines(&self, fractions: &[impl IntoFraction]) -> Result<String, &'static str> {
+        let fractions = checked(fractions)?;
+        let mut out = String::new();
+        write_json_lines(&mut out, None, self, &fractions)?;
+        Ok(out)
+    }
+}
+
+fn write_json_lines(
+    out: &mut String,
+    series: Option<&str>,
+    snapshot: &Snapshot,
+    fractions: &[f64],
+) -> Result<(), &'static str> {
+    let series = series.map_or(String::new(), |key| {
+        format!("\"series\":{},", json_string(key))
+    });
+    for (start, window) in snapshot.windows() {
+        if window.val_count == 0 {
+            continue;
+        }
+        let values = window.estimate_quantiles(fractions)?;
+        let end = start.saturating_add(snapshot.duration());
+        for (fraction, value) in fractions.iter().zip(values) {
+            let _ = writeln!(
+                out,
+                "{{{series}\"start\":{start},\"end\":{end},\"quantile\":{fraction},\"value\":{value},\"count\":{}}}",
+                window.val_count
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rounds `count` half up to `digits` significant digits.
+fn round_significant(count: usize, digits: u32) -> usize {
+    let length = count.checked_ilog10().map_or(1, |log| log + 1);
+    if digits == 0 || length <= digits {
+        return count;
+    }
+    let scale = 10usize.pow(length - digits);
+    (count + scale / 2) / scale * scale
+}
+
+/// Escapes a label value for the Prometheus text format.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Quotes `text` as a JSON string.
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("latency"), ("latency", vec![]));
+        assert_eq!(
+            parse_key("latency{tier=\"edge\", route=/api}"),
+            (
+                "latency",
+                vec![("tier", "edge".into()), ("route", "/api".into())]
+            )
+        );
+        assert_eq!(
+            parse_key("latency{stray, a=1}"),
+            ("latency", vec![("a", "1".into())])
+        );
+    }
+    #[test]
+    fn test_parse_key_round_trip() {
+        let values = ["x,y", "say \"hi\"", "C:\\temp", "two\nlines", "a=b"];
+        for value in values {
+            let escaped = escape_label_value(value);
+            let key = format!("latency{{a=\"{escaped}\",b=\"{escaped}\",tier=edge}}");
+            let (name, labels) = parse_key(&key);
+            assert_eq!(name, "latency");
+            assert_eq!(
+                labels,
+                vec![
+                    ("a", value.into()),
+                    ("b", value.into()),
+                    ("tier", "edge".into())
+                ]
+            );
+        }
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 100,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        registry.record("latency{a=\"x,y\\\"z\"}", 10, 0).unwrap();
+        let summary = registry
+            .prometheus_summary(&[0.5], &ExportFilter::new())
+            .unwrap();
+        assert!(
+            summary.contains("latency_count{a=\"x,y\\\"z\"} 1"),
+            "{summary}"
+        );
+    }
+    #[test]
+    fn test_filtered_export() {
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 100,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        registry.record("latency{tier=\"edge\"}", 10, 0).unwrap();
+        registry.record("latency{tier=\"core\"}", 20, 0).unwrap();
+        registry
+            .record("healthcheck_latency{tier=\"edge\"}", 1, 0)
+            .unwrap();
+
+        let filter = ExportFilter::new()
+            .include("tier", "edge")
+            .exclude(NAME_LABEL, "health*");
+        let keys: Vec<String> = registry
+            .snapshots(&filter)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, vec!["latency{tier=\"edge\"}"]);
+        assert_eq!(registry.snapshots(&ExportFilter::new()).len(), 3);
+
+        let text = registry
+            .prometheus_summary(
+                &[0.5, 0.99],
+                &ExportFilter::new().exclude(NAME_LABEL, "health*"),
+            )
+            .unwrap();
+        assert_eq!(
+            text,
+            "# TYPE latency summary\n\
+             latency{tier=\"core\",quantile=\"0.5\"} 20\n\
+             latency{tier=\"core\",quantile=\"0.99\"} 20\n\
+             latency_count{tier=\"core\"} 1\n\
+             latency{tier=\"edge\",quantile=\"0.5\"} 10\n\
+             latency{tier=\"edge\",quantile=\"0.99\"} 10\n\
+             latency_count{tier=\"edge\"} 1\n"
+        );
+        // Invalid fractions fail instead of dropping the quantile lines
+        let all = ExportFilter::new();
+        assert!(registry.prometheus_summary(&[0.5, f64::NAN], &all).is_err());
+        assert!(registry.prometheus_summary(&[1.5], &all).is_err());
+    }
+    #[test]
+    fn test_prometheus_families_are_contiguous() {
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 100,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        registry.record("latency", 1, 0).unwrap();
+        registry.record("latency_b", 2, 0).unwrap();
+        registry.record("latency{a=\"x\"}", 3, 0).unwrap();
+        registry.record("latency{a=x\\y\ny}", 4, 0).unwrap();
+        let text = registry
+            .prometheus_summary(&[0.5], &ExportFilter::new())
+            .unwrap();
+        assert_eq!(
+            text,
+            "# TYPE latency summary\n\
+             latency{quantile=\"0.5\"} 1\n\
+             latency_count 1\n\
+             latency{a=\"x\",quantile=\"0.5\"} 3\n\
+             latency_count{a=\"x\"} 1\n\
+             latency{a=\"x\\\\y\\ny\",quantile=\"0.5\"} 4\n\
+             latency_count{a=\"x\\\\y\\ny\"} 1\n\
+             # TYPE latency_b summary\n\
+             latency_b{quantile=\"0.5\"} 2\n\
+             latency_b_count 1\n"
+        );
+    }
+    #[test]
+    fn test_quantized_snapshot() {
+        assert_eq!(round_significant(0, 2), 0);
+        assert_eq!(round_significant(7, 2), 7);
+        assert_eq!(round_significant(1234, 2), 1200);
+        assert_eq!(round_significant(1250, 2), 1300);
+        assert_eq!(round_significant(999, 2), 1000);
+        let mut ring_buffer = crate::TimeBasedRingBuffer::new(2, 10, 0, 1000);
+        for v in 0..1000u64 {
+            for _ in 0..(v % 37 + 100) {
+                ring_buffer.insert(v, 0).unwrap();
+            }
+        }
+        let exact = ring_buffer.snapshot();
+        let quantized = exact.quantized(2);
+        let combined = exact.combined();
+        let n = combined.val_count as f64;
+        let epsilon = 0.05;
+        let bound = (1.0 + epsilon) / (1.0 - epsilon);
+        for fraction in [0.01, 0.5, 0.9, 0.99, 0.999] {
+            let estimate = quantized.estimate_quantile(fraction).unwrap();
+            let below = estimate.checked_sub(1).map_or(0, |v| combined.rank(v)) as f64;
+            let through = combined.rank(estimate) as f64;
+            // The estimate's bucket straddles a fraction within the documented factor
+            assert!(through / n >= fraction / bound && below / n <= fraction * bound);
+            assert!((n - below) / n >= (1.0 - fraction) / bound);
+            assert!((n - through) / n <= (1.0 - fraction) * bound);
+        }
+        assert_eq!(exact.quantized(10).combined().quantiles, combined.quantiles);
+    }
+    #[test]
+    fn test_json_lines() {
+        let config = SeriesConfig {
+            capacity: 3,
+            duration: 10,
+            start: 0,
+            end: 100,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        registry.record("latency{tier=\"edge\"}", 10, 0).unwrap();
+        registry.record("latency{tier=\"edge\"}", 30, 25).unwrap();
+        let text = registry.json_lines(&[0.5], &ExportFilter::new()).unwrap();
+        assert_eq!(
+            text,
+            "{\"series\":\"latency{tier=\\\"edge\\\"}\",\"start\":0,\"end\":10,\"quantile\":0.5,\"value\":10,\"count\":1}\n\
+             {\"series\":\"latency{tier=\\\"edge\\\"}\",\"start\":20,\"end\":30,\"quantile\":0.5,\"value\":30,\"count\":1}\n"
+        );
+        let snapshot = registry.get("latency{tier=\"edge\"}").unwrap().snapshot();
+        assert_eq!(snapshot.json_lines(&[0.0, 1.0]).unwrap().lines().count(), 4);
+        assert!(snapshot.json_lines(&[-0.1]).is_err());
+        assert!(
+            registry
+                .json_lines(&[f64::NAN], &ExportFilter::new())
+                .is_err()
+        );
+        assert_eq!(json_string("a\\b\n"), "\"a\\\\b\\u000a\"");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use crate::registry::SeriesConfig;
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+type Facets = BTreeMap<String, TimeBasedRingBuffer>;
+
+/// Records each value into an "all" rollup and into one ring buffer per facet, such as
+/// `region=eu` or `status=500`, from a single stream of tagged values.
+///
+/// Every record carries exactly one value per declared dimension, and all buffers share
+/// one clock, so for every dimension the facets' windows merge to exactly the rollup's.
+#[derive(Debug)]
+pub struct FacetedRecorder {
+    rollup: TimeBasedRingBuffer,
+    dimensions: Vec<(String, Facets)>,
+    config: SeriesConfig,
+}
+
+/// A [`FacetedRecorder`] with fixed tags, from [`FacetedRecorder::tagged`].
+#[derive(Debug)]
+pub struct TaggedRecorder<'a> {
+    pub(crate) recorder: &'a mut FacetedRecorder,
+    pub(crate) tags: &'a [(&'a str, &'a str)],
+}
+
+impl FacetedRecorder {
+    /// Creates a recorder splitting values along `dimensions`, with every buffer sharing
+    /// the same configuration. See [`TimeBasedRingBuffer::new`].
+    pub fn new(dimensions: &[&str], capacity: usize, duration: u64, start: u64, end: u64) -> Self {
+        FacetedRecorder {
+            rollup: TimeBasedRingBuffer::new(capacity, duration, start, end),
+            dimensions: dimensions
+                .iter()
+                .map(|&name| (name.to_string(), Facets::new()))
+                .collect(),
+            config: SeriesConfig {
+                capacity,
+                duration,
+                start,
+                end,
+            },
+        }
+    }
+
+    /// Returns a handle recording every value with `tags`, for passing to code that takes
+    /// a [`Record`](crate::Record). Untimed values go into the rollup's current window.
+    pub fn tagged<'a>(&'a mut self, tags: &'a [(&'a str, &'a str)]) -> TaggedRecorder<'a> {
+        TaggedRecorder {
+            recorder: self,
+            tags,
+        }
+    }
+
+    /// Records `value` at `timestamp` into the rollup and into the facet named by each
+    /// tag. `tags` must hold one `(dimension, value)` pair per declared dimension, in any
+    /// order; otherwise nothing is recorded.
+    pub fn record(
+        &mut self,
+        value: u64,
+        timestamp: u64,
+        tags: &[(&str, &str)],
+    ) -> Result<(), &'static str> {
+        let SeriesConfig {
+            capacity,
+            duration,
+            start,
+            end,
+        } = self.config;
+        if value < start || value > end {
+            return Err("Value out of range");
+        }
+        if duration == 0 {
+            return Err("Duration must be greater than zero");
+        }
+        if tags.len() != self.dimensions.len() {
+            return Err("Expected one tag per dimension");
+        }
+        let mut facets = Vec::with_capacity(tags.len());
+        for (name, _) in &self.dimensions {
+            let (_, facet) = tags
+                .iter()
+                .find(|(dimension, _)| dimension == name)
+                .ok_or("Expected one tag per dimension")?;
+            facets.push(*facet);
+        }
+        // Checked above, so nothing below can fail halfway through.
+        self.rollup.insert(value, timestamp)?;
+        for ((_, buffers), facet) in self.dimensions.iter_mut().zip(facets) {
+            if !buffers.contains_key(facet) {
+                let buffer = TimeBasedRingBuffer::new(capacity, duration, start, end);
+                buffers.insert(facet.to_string(), buffer);
+            }
+            for (name, buffer) in buffers.iter_mut() {
+                if name == facet {
+                    buffer.insert(value, timestamp)?;
+                } else {
+                    buffer.advance(timestamp)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the buffer holding every value recorded.
+    pub fn rollup(&self) -> &TimeBasedRingBuffer {
+        &self.rollup
+    }
+
+    /// Returns the buffer of one facet, e.g. `facet("region", "eu")`.
+    pub fn facet(&self, dimension: &str, value: &str) -> Option<&TimeBasedRingBuffer> {
+        self.facets(dimension)?
+            .find(|(name, _)| *name == value)
+            .map(|(_, buffer)| buffer)
+    }
+
+    /// Returns every facet of `dimension` seen so far, sorted by value.
+    pub fn facets(
+        &self,
+        dimension: &str,
+    ) -> Option<impl Iterator<Item = (&str, &TimeBasedRingBuffer)>> {
+        let (_, buffers) = self.dimensions.iter().find(|(name, _)| name == dimension)?;
+        Some(buffers.iter().map(|(name, buffer)| (name.as_str(), buffer)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_facets_merge_to_rollup() {
+        let mut recorder = FacetedRecorder::new(&["region", "status"], 3, 10, 0, 1000);
+        let regions = ["eu", "us", "ap"];
+        let statuses = ["200", "500"];
+        for i in 0..200u64 {
+            let tags = [
+                ("status", statuses[(i % 7 % 2) as usize]),
+                ("region", regions[(i % 3) as usize]),
+            ];
+            // The "ap" facet stops receiving values halfway, but still rotates
+            let tags = if i >= 60 && tags[1].1 == "ap" {
+                [tags[0], ("region", "eu")]
+            } else {
+                tags
+            };
+            recorder.record(i * 7 % 1000, i / 4, &tags).unwrap();
+        }
+        for dimension in ["region", "status"] {
+            let mut merged = None;
+            for (_, buffer) in recorder.facets(dimension).unwrap() {
+                let snapshot = buffer.snapshot();
+                match &mut merged {
+                    None => merged = Some(snapshot),
+                    Some(merged) => merged.merge(&snapshot).unwrap(),
+                }
+            }
+            let merged = merged.unwrap();
+            let rollup = recorder.rollup().snapshot();
+            assert_eq!(merged.windows().len(), rollup.windows().len());
+            for ((a, x), (b, y)) in merged.windows().iter().zip(rollup.windows()) {
+                assert_eq!((a, &x.quantiles), (b, &y.quantiles));
+            }
+        }
+        assert_eq!(recorder.facets("region").unwrap().count(), 3);
+        let ap = recorder.facet("region", "ap").unwrap();
+        assert!(ap.estimate_quantile(0.5).is_err());
+        assert!(recorder.facet("region", "sa").is_none());
+
+        assert!(recorder.record(1, 50, &[("region", "eu")]).is_err());
+        assert!(
+            recorder
+                .record(1, 50, &[("region", "eu"), ("code", "1")])
+                .is_err()
+        );
+        assert!(
+            recorder
+                .record(5000, 50, &[("region", "eu"), ("status", "200")])
+                .is_err()
+        );
+        assert_eq!(recorder.rollup().windows().last().unwrap().0, 40);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+
+use crate::snapshot::percentile_label;
+
+/// A quantile fraction, checked once at construction to lie in `0.0..=1.0`.
+///
+/// Query methods accept either a `Fraction` or a plain `f64` through [`IntoFraction`];
+/// passing a `Fraction` (or one of the constants) means the range check can't fail.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Fraction(f64);
+
+impl Fraction {
+    pub const MIN: Fraction = Fraction(0.0);
+    pub const P50: Fraction = Fraction(0.5);
+    pub const P90: Fraction = Fraction(0.9);
+    pub const P95: Fraction = Fraction(0.95);
+    pub const P99: Fraction = Fraction(0.99);
+    pub const P999: Fraction = Fraction(0.999);
+    pub const MAX: Fraction = Fraction(1.0);
+
+    /// Returns the fraction, or an error if it is outside `0.0..=1.0` or NaN.
+    pub fn new(value: f64) -> Result<Self, &'static str> {
+        if !(0.0..=1.0).contains(&value) {
+            return Err("Fraction must be between 0 and 1");
+        }
+        Ok(Fraction(value))
+    }
+
+    /// Returns the fraction as a float.
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<Fraction> for f64 {
+    fn from(fraction: Fraction) -> f64 {
+        fraction.0
+    }
+}
+
+impl TryFrom<f64> for Fraction {
+    type Error = &'static str;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        Fraction::new(value)
+    }
+}
+
+/// Formats as a percentile label, e.g. `p99.9`.
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&percentile_label(self.0))
+    }
+}
+
+/// Anything query methods accept as a fraction: a [`Fraction`], or an `f64` that is
+/// checked on use.
+pub trait IntoFraction: Copy + sealed::Sealed {
+    fn into_fraction(self) -> Result<Fraction, &'static str>;
+}
+
+impl IntoFraction for Fraction {
+    fn into_fraction(self) -> Result<Fraction, &'static str> {
+        Ok(self)
+    }
+}
+
+impl IntoFraction for f64 {
+    fn into_fraction(self) -> Result<Fraction, &'static str> {
+        Fraction::new(self)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::Fraction {}
+    impl Sealed for f64 {}
+}
+
+/// Checks every fraction of a slice, returning them as floats.
+pub(crate) fn checked(fractions: &[impl IntoFraction]) -> Result<Vec<f64>, &'static str> {
+    fractions
+        .iter()
+        .map(|f| f.into_fraction().map(Fraction::get))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::estimator::QuantileEstimator;
+    #[test]
+    fn test_fraction() {
+        assert_eq!(Fraction::new(0.99), Ok(Fraction::P99));
+        assert!(Fraction::new(1.5).is_err());
+        assert!(Fraction::new(f64::NAN).is_err());
+        assert_eq!(f64::from(Fraction::P999), 0.999);
+        assert_eq!(Fraction::try_from(0.5), Ok(Fraction::P50));
+        assert_eq!(Fraction::P999.to_string(), "p99.9");
+        assert_eq!(checked(&[0.5, 1.0]), Ok(vec![0.5, 1.0]));
+        assert!(checked(&[0.5, -1.0]).is_err());
+
+        let mut estimator = QuantileEstimator::new(0, 100);
+        for v in 1..=100 {
+            estimator.add_value(v).unwrap();
+        }
+        assert_eq!(estimator.estimate_quantile(Fraction::P99).unwrap(), 99);
+        assert_eq!(estimator.estimate_quantile(0.99).unwrap(), 99);
+        assert_eq!(
+            estimator
+                .estimate_quantiles(&[Fraction::MIN, Fraction::P50, Fraction::MAX])
+                .unwrap(),
+            vec![1, 50, 100]
+        );
+    }
+}
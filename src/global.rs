@@ -0,0 +1,118 @@
+//! A process-wide registry for applications that would rather not pass handles around.
+//!
+//! Install a registry once with [`init`], then record and query by key from anywhere
+//! with [`record_quantile!`](crate::record_quantile) and [`quantile!`](crate::quantile).
+//! Timestamps come from the configured clock, seconds since the Unix epoch by default.
+
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use crate::fraction::IntoFraction;
+use crate::pipeline::unix_seconds;
+use crate::registry::QuantileRegistry;
+
+type Clock = fn() -> u64;
+
+struct Global {
+    registry: Mutex<Option<QuantileRegistry>>,
+    clock: Clock,
+}
+
+static GLOBAL: OnceLock<Global> = OnceLock::new();
+
+/// Installs the global registry, timestamping values in seconds since the Unix epoch.
+/// Fails if a registry was already installed, even if it has since been shut down.
+pub fn init(registry: QuantileRegistry) -> Result<(), &'static str> {
+    init_with_clock(registry, unix_seconds)
+}
+
+/// Installs the global registry with a custom clock, e.g. one returning milliseconds to
+/// match the series' window durations.
+pub fn init_with_clock(registry: QuantileRegistry, clock: Clock) -> Result<(), &'static str> {
+    let global = Global {
+        registry: Mutex::new(Some(registry)),
+        clock,
+    };
+    GLOBAL
+        .set(global)
+        .map_err(|_| "Global registry is already initialized")
+}
+
+/// Records `value` for the series `key` at the current time.
+pub fn record(key: &str, value: u64) -> Result<(), &'static str> {
+    let global = GLOBAL.get().ok_or("Global registry is not initialized")?;
+    let timestamp = (global.clock)();
+    registry(global)?
+        .as_mut()
+        .ok_or("Global registry has been shut down")?
+        .record(key, value, timestamp)
+}
+
+/// Returns the quantile of the series `key` over its retained windows.
+pub fn quantile(key: &str, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+    let global = GLOBAL.get().ok_or("Global registry is not initialized")?;
+    registry(global)?
+        .as_ref()
+        .ok_or("Global registry has been shut down")?
+        .get(key)
+        .ok_or("No such series")?
+        .estimate_quantile(fraction)
+}
+
+/// Takes the registry out so its final state can be exported. Later records and queries
+/// fail instead of being silently lost.
+pub fn shutdown() -> Option<QuantileRegistry> {
+    registry(GLOBAL.get()?).ok()?.take()
+}
+
+fn registry(global: &Global) -> Result<MutexGuard<'_, Option<QuantileRegistry>>, &'static str> {
+    global
+        .registry
+        .lock()
+        .map_err(|_| "Global registry lock poisoned")
+}
+
+/// Records a value into the global registry: `record_quantile!("latency", 42)`.
+#[macro_export]
+macro_rules! record_quantile {
+    ($key:expr, $value:expr) => {
+        $crate::global::record($key, $value)
+    };
+}
+
+/// Queries the global registry: `quantile!("latency", 0.99)`.
+#[macro_export]
+macro_rules! quantile {
+    ($key:expr, $fraction:expr) => {
+        $crate::global::quantile($key, $fraction)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    #[test]
+    fn test_global_registry() {
+        assert!(record_quantile!("latency", 1).is_err());
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 100,
+        };
+        init_with_clock(QuantileRegistry::builder(config).build(), || 5).unwrap();
+        assert!(init(QuantileRegistry::builder(config).build()).is_err());
+        for value in [10, 20, 30] {
+            record_quantile!("latency", value).unwrap();
+        }
+        assert_eq!(quantile!("latency", 0.5).unwrap(), 20);
+        assert!(quantile!("missing", 0.5).is_err());
+        let registry = shutdown().unwrap();
+        assert_eq!(
+            registry.get("latency").unwrap().estimate_quantile(1.0),
+            Ok(30)
+        );
+        assert!(record_quantile!("latency", 1).is_err());
+        assert!(shutdown().is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! Quantile estimation over data streams, with sliding window support through a
+//! time-based ring buffer of per-window estimators.
+
+mod annotation;
+mod anonymize;
+mod audit;
+pub mod bench;
+mod buckets;
+mod concurrent;
+mod delta;
+mod domain;
+#[cfg(feature = "unstable")]
+mod dual;
+mod estimator;
+mod exclusion;
+mod export;
+mod faceted;
+mod fraction;
+#[cfg(feature = "global")]
+pub mod global;
+mod memory;
+mod merge;
+#[cfg(feature = "overhead")]
+mod overhead;
+mod paired;
+mod parse;
+mod pipeline;
+mod provenance;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+mod record;
+mod registry;
+mod report;
+mod ring_buffer;
+mod rotation;
+mod series;
+mod shape;
+mod sharded;
+mod snapshot;
+mod staged;
+pub mod testing;
+mod validate;
+
+pub use annotation::Annotation;
+pub use anonymize::Anonymizer;
+pub use audit::{AuditEntry, AuditEvent};
+pub use concurrent::ConcurrentRingBuffer;
+pub use delta::{DeltaParts, SnapshotDelta, WindowDelta};
+#[cfg(feature = "unstable")]
+pub use dual::DualResolutionEstimator;
+pub use estimator::QuantileEstimator;
+pub use exclusion::Exclusion;
+pub use export::{ExportFilter, NAME_LABEL, parse_key};
+pub use faceted::{FacetedRecorder, TaggedRecorder};
+pub use fraction::{Fraction, IntoFraction};
+pub use memory::Degradation;
+pub use merge::{merge_all, merge_streaming, select_quantile};
+#[cfg(feature = "overhead")]
+pub use overhead::overhead_report;
+pub use paired::{PairedTracker, TimeoutPolicy};
+pub use parse::{ParseError, ParseErrorKind, parse_quantile, parse_quantiles};
+pub use pipeline::{Alert, Pipeline, PipelineBuilder, PipelineRecorder, PipelineSeries, Tick};
+pub use provenance::Provenance;
+pub use record::Record;
+pub use registry::{
+    NewSeries, QuantileRegistry, QuantileRegistryBuilder, SeriesConfig, SeriesRecorder,
+};
+pub use report::{Interpolation, QuantileReport};
+pub use ring_buffer::{ShrinkPolicy, TimeBasedRingBuffer};
+pub use rotation::Rotation;
+pub use series::{Band, Direction, Excursion};
+pub use shape::Mode;
+pub use sharded::{ShardStats, ShardStrategy, ShardedRingBuffer};
+pub use snapshot::Snapshot;
+pub use staged::{StageGrowth, StageRecorder, StagedTracker};
+pub use validate::{ValidationIssue, ValidationReport, WindowParts};
+
+/// Buffers and registries are shared between threads behind `Arc` and `RwLock`, so every
+/// field they hold, hooks included, must stay `Sync`.
+fn _assert_sync<T: Sync>() {}
+const _: fn() = || {
+    _assert_sync::<TimeBasedRingBuffer>();
+    _assert_sync::<QuantileRegistry>();
+};
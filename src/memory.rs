@@ -0,0 +1,182 @@
+use crate::audit::{self, AuditEvent};
+use crate::registry::QuantileRegistry;
+use crate::ring_buffer::ShrinkPolicy;
+
+/// How far a registry with a memory cap has degraded to stay under it. Levels are
+/// reached in this order and never undone.
+///
+/// Series keep no exemplars and buckets always have unit width, so the first lever is
+/// retention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Degradation {
+    /// Everything fits.
+    None,
+    /// Every series' retention has been halved, possibly several times, down to a
+    /// single window. New series are created with the shortened retention.
+    ShortenedRetention { capacity: usize },
+    /// Retention is down to one window and the least recently written series have been
+    /// evicted.
+    EvictedSeries { evicted: usize },
+}
+
+impl QuantileRegistry {
+    /// Returns the approximate number of bytes held by all series.
+    pub fn memory_usage(&self) -> usize {
+        self.series.values().map(|s| s.memory_usage()).sum()
+    }
+
+    /// Returns how far the registry has degraded to stay under its memory cap.
+    pub fn degradation(&self) -> Degradation {
+        self.degradation
+    }
+
+    /// Degrades until the series fit under the memory cap, never evicting `keep`, the
+    /// series just created or grown. Fails, removing `keep`, if it alone exceeds the cap.
+    pub(crate) fn enforce_memory_cap(
+        &mut self,
+        keep: &str,
+        timestamp: u64,
+    ) -> Result<(), &'static str> {
+        let Some(cap) = self.memory_cap else {
+            return Ok(());
+        };
+        while self.memory_usage() > cap {
+            let longest = self
+                .series
+                .values()
+                .map(|s| s.capacity())
+                .max()
+                .unwrap_or(0);
+            if longest > 1 {
+                let capacity = longest / 2;
+                for series in self.series.values_mut() {
+                    if series.capacity() > capacity {
+                        series.resize(capacity, ShrinkPolicy::Drop)?;
+                    }
+                }
+                self.retention_limit = Some(capacity);
+                self.degradation = Degradation::ShortenedRetention { capacity };
+                let event = AuditEvent::RetentionShortened { capacity };
+                audit::push(&mut self.audit, timestamp, event);
+                continue;
+            }
+            // Evict the series whose current window is the oldest
+            let stalest = self
+                .series
+                .iter()
+                .filter(|(key, _)| *key != keep)
+                .min_by_key(|(key, series)| (series.current_window_start(), key.as_str()))
+                .map(|(key, _)| key.clone());
+            let Some(stalest) = stalest else {
+                self.series.remove(keep);
+                return Err("Series exceeds the memory cap");
+            };
+            self.series.remove(&stalest);
+            let event = AuditEvent::SeriesEvicted { key: stalest };
+            audit::push(&mut self.audit, timestamp, event);
+            let evicted = match self.degradation {
+                Degradation::EvictedSeries { evicted } => evicted + 1,
+                _ => 1,
+            };
+            self.degradation = Degradation::EvictedSeries { evicted };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    #[test]
+    fn test_memory_cap_degradation_order() {
+        let config = SeriesConfig {
+            capacity: 8,
+            duration: 10,
+            start: 0,
+            end: 1023,
+        };
+        let mut unbounded = QuantileRegistry::builder(config).build();
+        unbounded.record("a", 1, 0).unwrap();
+        let series = unbounded.memory_usage();
+        assert_eq!(unbounded.degradation(), Degradation::None);
+
+        // Room for two full series: the third shortens retention to half
+        let mut registry = QuantileRegistry::builder(config)
+            .memory_cap(series * 5 / 2)
+            .build();
+        for (key, ts) in [("a", 0), ("b", 10), ("c", 20)] {
+            registry.record(key, 1, ts).unwrap();
+        }
+        assert_eq!(
+            registry.degradation(),
+            Degradation::ShortenedRetention { capacity: 4 }
+        );
+        assert!(registry.memory_usage() <= series * 5 / 2);
+        assert_eq!(registry.get("a").unwrap().capacity(), 4);
+        registry.record("d", 1, 30).unwrap();
+        assert_eq!(registry.get("d").unwrap().capacity(), 4);
+
+        // Many more series end up at one window each, then evict the stalest
+        for i in 0..30 {
+            registry.record(&format!("s{i}"), 1, 40 + i * 10).unwrap();
+        }
+        assert!(matches!(
+            registry.degradation(),
+            Degradation::EvictedSeries { .. }
+        ));
+        assert!(registry.memory_usage() <= series * 5 / 2);
+        assert!(registry.get("a").is_none());
+        assert!(registry.get("s29").is_some());
+        assert!(
+            Degradation::ShortenedRetention { capacity: 1 }
+                < Degradation::EvictedSeries { evicted: 1 }
+        );
+
+        let mut tiny = QuantileRegistry::builder(config).memory_cap(16).build();
+        assert!(tiny.record("a", 1, 0).is_err());
+        assert!(tiny.is_empty());
+    }
+    #[test]
+    fn test_memory_cap_holds_as_series_grow() {
+        let config = SeriesConfig {
+            capacity: 4,
+            duration: 10,
+            start: 0,
+            end: 1023,
+        };
+        let mut unbounded = QuantileRegistry::builder(config).build();
+        unbounded.record("a", 1, 0).unwrap();
+        unbounded.record("b", 1, 0).unwrap();
+        let created = unbounded.memory_usage();
+
+        // Two series fit as created, but not once one turns dense and is charged for its
+        // spare buckets
+        let cap = created + created / 16;
+        let mut registry = QuantileRegistry::builder(config).memory_cap(cap).build();
+        registry.record("a", 1, 0).unwrap();
+        registry.record("b", 1, 0).unwrap();
+        assert_eq!(registry.degradation(), Degradation::None);
+        for v in 0..1024 {
+            registry.record("a", v, 0).unwrap();
+        }
+        assert!(registry.memory_usage() <= cap);
+        assert_ne!(registry.degradation(), Degradation::None);
+        assert!(registry.get("a").is_some());
+
+        // Annotations scheduled by a rotation hook grow the series too
+        let mut registry = QuantileRegistry::builder(config)
+            .memory_cap(created)
+            .build();
+        registry.record("a", 1, 0).unwrap();
+        registry.record("b", 1, 0).unwrap();
+        registry.series.get_mut("a").unwrap().on_rotate(|rotation| {
+            let at = rotation.next_window_start();
+            rotation.schedule(move |buffer| buffer.annotate(at, "rotated"));
+        });
+        registry.record("a", 1, 10).unwrap();
+        assert!(registry.memory_usage() <= created);
+        assert_ne!(registry.degradation(), Degradation::None);
+    }
+}
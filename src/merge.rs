@@ -0,0 +1,247 @@
+use crate::estimator::{QuantileEstimator, RANK_BLOCK, rank_index};
+use crate::fraction::IntoFraction;
+use crate::snapshot::Snapshot;
+
+/// Number of buckets merged across all estimators at a time.
+const MERGE_BLOCK: usize = 1024;
+
+/// Smallest bucket range worth handing to a separate thread.
+#[cfg(feature = "parallel")]
+const MIN_PARALLEL_CHUNK: usize = 16 * MERGE_BLOCK;
+
+/// Combines many estimators covering the same range into one.
+///
+/// With the opt-in `parallel` feature the bucket range is split across std scoped
+/// threads, each summing its share of buckets over every estimator. Without it the
+/// merge runs on the calling thread and no threads are spawned. Counts are integers, so
+/// the result is identical either way.
+pub fn merge_all<'a, I>(estimators: I) -> Result<QuantileEstimator, &'static str>
+where
+    I: IntoIterator<Item = &'a QuantileEstimator>,
+{
+    let estimators: Vec<&QuantileEstimator> = estimators.into_iter().collect();
+    let first = *estimators.first().ok_or("No estimators to merge")?;
+    if estimators
+        .iter()
+        .any(|e| e.start != first.start || e.end != first.end)
+    {
+        return Err("Estimator ranges do not match");
+    }
+    let counts = sum_buckets(&estimators, first.quantiles.len());
+    let mut merged = QuantileEstimator::from_counts(first.start, first.end, counts);
+    for estimator in &estimators {
+        merged.inherit_error(estimator);
+    }
+    Ok(merged)
+}
+
+/// Folds snapshots into a single accumulator one at a time, so only one input snapshot
+/// is held in memory at once.
+///
+/// Only the windows are merged, so the accumulator holds one estimator per distinct
+/// window start however many snapshots go in. Contributors, annotations and audit
+/// entries grow with every input and are dropped; use [`Snapshot::merge`] to keep them.
+pub fn merge_streaming<I>(snapshots: I) -> Result<Snapshot, &'static str>
+where
+    I: IntoIterator<Item = Snapshot>,
+{
+    let mut snapshots = snapshots.into_iter();
+    let mut merged = snapshots.next().ok_or("No snapshots to merge")?;
+    merged.contributors = Vec::new();
+    merged.contributor_index = None;
+    merged.annotations = Vec::new();
+    merged.audit = Vec::new();
+    for snapshot in snapshots {
+        merged.merge_windows(&snapshot)?;
+    }
+    Ok(merged)
+}
+
+/// Estimates the quantile of the combined distribution of many estimators covering the
+/// same range, without materializing the combined histogram.
+///
+/// The rank is first located at block granularity from each estimator's block totals,
+/// then resolved bucket by bucket inside the selected block, so a query costs
+/// O(estimators × (blocks + block size)) instead of O(estimators × buckets).
+pub fn select_quantile<'a, I>(
+    estimators: I,
+    fraction: impl IntoFraction,
+) -> Result<u64, &'static str>
+where
+    I: IntoIterator<Item = &'a QuantileEstimator>,
+{
+    let fraction = fraction.into_fraction()?.get();
+    let estimators: Vec<&QuantileEstimator> = estimators.into_iter().collect();
+    let first = *estimators.first().ok_or("No estimators to query")?;
+    if estimators
+        .iter()
+        .any(|e| e.start != first.start || e.end != first.end)
+    {
+        return Err("Estimator ranges do not match");
+    }
+    let total: usize = estimators.iter().map(|e| e.val_count).sum();
+    if total == 0 {
+        return Err("No values added to any estimator");
+    }
+    let index = rank_index(fraction, total);
+    let used = estimators.iter().filter(|e| e.val_count > 0);
+    if index == 0 {
+        return Ok(used.map(|e| e.min).min().unwrap_or(first.start));
+    }
+    if index + 1 == total {
+        return Ok(used.map(|e| e.max).max().unwrap_or(first.start));
+    }
+    let mut cumulative = 0;
+    for block in 0..first.block_counts.len() {
+        let block_total: usize = estimators.iter().map(|e| e.block_counts[block]).sum();
+        if cumulative + block_total <= index {
+            cumulative += block_total;
+            continue;
+        }
+        let block_start = block * RANK_BLOCK;
+        let block_end = (block_start + RANK_BLOCK).min(first.quantiles.len());
+        for i in block_start..block_end {
+            cumulative += estimators.iter().map(|e| e.quantiles[i]).sum::<usize>();
+            if cumulative > index {
+                return Ok(first.start + i as u64);
+            }
+        }
+    }
+    Err("No quantile found for the given fraction")
+}
+
+/// Estimates the quantile over `[from, to)` from windows of `duration` overlapping it.
+/// Windows only partially inside the range are scaled by the overlapping fraction.
+pub(crate) fn select_between<'a>(
+    windows: impl Iterator<Item = (u64, &'a QuantileEstimator)>,
+    duration: u64,
+    fraction: f64,
+    from: u64,
+    to: u64,
+) -> Result<u64, &'static str> {
+    let mut whole = Vec::new();
+    let mut partial = Vec::new();
+    for (start, window) in windows {
+        let overlap = (start.saturating_add(duration)).min(to) - start.max(from);
+        if overlap == duration {
+            whole.push(window);
+        } else {
+            partial.push(window.scaled(overlap, duration));
+        }
+    }
+    if whole.is_empty() && partial.is_empty() {
+        return Err("No windows in the requested time range");
+    }
+    select_quantile(whole.into_iter().chain(&partial), fraction)
+}
+
+#[cfg(not(feature = "parallel"))]
+fn sum_buckets(estimators: &[&QuantileEstimator], len: usize) -> Vec<usize> {
+    let mut combined = vec![0; len];
+    sum_range(estimators, 0, &mut combined);
+    combined
+}
+
+#[cfg(feature = "parallel")]
+fn sum_buckets(estimators: &[&QuantileEstimator], len: usize) -> Vec<usize> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = len.div_ceil(threads).max(MIN_PARALLEL_CHUNK);
+    let mut combined = vec![0; len];
+    std::thread::scope(|scope| {
+        for (i, out) in combined.chunks_mut(chunk).enumerate() {
+            scope.spawn(move || sum_range(estimators, i * chunk, out));
+        }
+    });
+    combined
+}
+
+/// Adds the buckets starting at `offset` of every estimator into `out`, one
+/// `MERGE_BLOCK` at a time so each block stays in cache across estimators.
+fn sum_range(estimators: &[&QuantileEstimator], offset: usize, out: &mut [usize]) {
+    for (b, block) in out.chunks_mut(MERGE_BLOCK).enumerate() {
+        let block_start = offset + b * MERGE_BLOCK;
+        for estimator in estimators {
+            let counts = &estimator.quantiles[block_start..block_start + block.len()];
+            for (sum, &count) in block.iter_mut().zip(counts) {
+                *sum += count;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_merge_all() {
+        let mut estimators = vec![QuantileEstimator::new(0, 100_000); 8];
+        for (i, estimator) in estimators.iter_mut().enumerate() {
+            for v in 0..1000 {
+                estimator.add_value(v * 100 + i as u64).unwrap();
+            }
+        }
+        let merged = merge_all(&estimators).unwrap();
+        let mut sequential = QuantileEstimator::new(0, 100_000);
+        for estimator in &estimators {
+            sequential.merge(estimator).unwrap();
+        }
+        assert_eq!(merged.quantiles, sequential.quantiles);
+        assert_eq!(merged.val_count, 8000);
+        assert_eq!(
+            merged.estimate_quantile(0.5).unwrap(),
+            sequential.estimate_quantile(0.5).unwrap()
+        );
+        assert!(merge_all(&[]).is_err());
+        estimators.push(QuantileEstimator::new(0, 10));
+        assert!(merge_all(&estimators).is_err());
+    }
+    #[test]
+    fn test_select_quantile_matches_merge() {
+        let mut estimators = vec![QuantileEstimator::new(0, 50_000); 5];
+        for (i, estimator) in estimators.iter_mut().enumerate() {
+            for v in 0..200 {
+                estimator
+                    .add_value((v * 37 + i as u64 * 4001) % 50_001)
+                    .unwrap();
+            }
+        }
+        // An empty estimator must not contribute its unset extremes
+        estimators.push(QuantileEstimator::new(0, 50_000));
+        let merged = merge_all(&estimators).unwrap();
+        for fraction in [0.0, 0.1, 0.25, 0.5, 0.9, 0.999, 1.0] {
+            assert_eq!(
+                select_quantile(&estimators, fraction).unwrap(),
+                merged.estimate_quantile(fraction).unwrap()
+            );
+        }
+        assert!(select_quantile(&estimators, 1.5).is_err());
+        assert!(select_quantile(&[QuantileEstimator::new(0, 10)], 0.5).is_err());
+    }
+    #[test]
+    fn test_merge_streaming() {
+        use crate::provenance::Provenance;
+        use crate::ring_buffer::TimeBasedRingBuffer;
+        let hosts = (0..100u64).map(|host| {
+            let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+            ring_buffer.insert(host, 5).unwrap();
+            ring_buffer.annotate(5, format!("deploy {host}"));
+            ring_buffer.insert(host + 500, 15).unwrap();
+            ring_buffer
+                .snapshot()
+                .with_provenance(Provenance::current(host))
+        });
+        let merged = merge_streaming(hosts).unwrap();
+        // Nothing in the accumulator grows with the number of inputs
+        assert_eq!(merged.windows().len(), 2);
+        assert!(merged.contributors().is_empty());
+        assert!(merged.annotations().is_empty());
+        assert!(merged.audit_log().is_empty());
+        assert_eq!(merged.combined().val_count, 200);
+        assert_eq!(merged.estimate_quantile(0.5).unwrap(), 99);
+        assert_eq!(merged.estimate_quantile(1.0).unwrap(), 599);
+        assert!(merge_streaming(std::iter::empty()).is_err());
+        let other = TimeBasedRingBuffer::new(3, 20, 0, 1000).snapshot();
+        let same = TimeBasedRingBuffer::new(3, 10, 0, 1000).snapshot();
+        assert!(merge_streaming([same, other]).is_err());
+    }
+}
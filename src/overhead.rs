@@ -0,0 +1,93 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::estimator::QuantileEstimator;
+use crate::snapshot::percentile_label;
+
+/// Longest operation tracked, in nanoseconds. Slower ones are counted at this value.
+const MAX_NANOS: u64 = 100_000;
+
+/// The operations whose cost is measured.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operation {
+    Insert,
+    Query,
+}
+
+static OVERHEAD: Mutex<Option<[QuantileEstimator; 2]>> = Mutex::new(None);
+
+/// Times an operation from creation until drop.
+pub(crate) struct Timer {
+    operation: Operation,
+    started: Instant,
+}
+
+impl Timer {
+    pub(crate) fn start(operation: Operation) -> Self {
+        Timer {
+            operation,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let nanos = u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        if let Ok(mut overhead) = OVERHEAD.lock() {
+            let estimators = overhead.get_or_insert_with(|| {
+                [
+                    QuantileEstimator::new(0, MAX_NANOS),
+                    QuantileEstimator::new(0, MAX_NANOS),
+                ]
+            });
+            let _ = estimators[self.operation as usize].add_value(nanos.min(MAX_NANOS));
+        }
+    }
+}
+
+/// Reports the time spent in ring buffer inserts and quantile queries so far, in
+/// nanoseconds, as the count and p50/p99/p100 of each.
+pub fn overhead_report() -> String {
+    const FRACTIONS: [f64; 3] = [0.5, 0.99, 1.0];
+    let mut out = String::new();
+    let Ok(overhead) = OVERHEAD.lock() else {
+        return out;
+    };
+    for (name, operation) in [("insert", Operation::Insert), ("query", Operation::Query)] {
+        let estimator = overhead.as_ref().map(|e| &e[operation as usize]);
+        let _ = write!(
+            out,
+            "{name}: count {}",
+            estimator.map_or(0, |e| e.val_count)
+        );
+        if let Some(Ok(values)) = estimator.map(|e| e.estimate_quantiles(&FRACTIONS)) {
+            for (fraction, value) in FRACTIONS.into_iter().zip(values) {
+                let _ = write!(out, ", {} {value}ns", percentile_label(fraction));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring_buffer::TimeBasedRingBuffer;
+    #[test]
+    fn test_overhead_report() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, 10, 0, 100);
+        for ts in 0..20 {
+            ring_buffer.insert(ts % 100, ts).unwrap();
+        }
+        ring_buffer.estimate_quantile(0.5).unwrap();
+        let report = overhead_report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("insert: count "));
+        assert!(lines[0].contains("p99 "));
+        assert!(lines[1].starts_with("query: count "));
+    }
+}
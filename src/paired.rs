@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+/// Correlates `start`/`end` events by request id and records the resulting durations
+/// into a ring buffer, so event-stream consumers don't have to pair events themselves.
+///
+/// At most `max_pending` requests are tracked at once; starting another one evicts the
+/// oldest. Requests still pending `timeout` after their start time out and are handled
+/// according to the [`TimeoutPolicy`]. Both are counted.
+#[derive(Debug)]
+pub struct PairedTracker<K> {
+    buffer: TimeBasedRingBuffer,
+    pending: HashMap<K, u64>,
+    /// Start events in arrival order. Entries whose request already ended or was
+    /// restarted are skipped lazily.
+    order: VecDeque<(u64, K)>,
+    max_pending: usize,
+    timeout: u64,
+    policy: TimeoutPolicy,
+    timed_out: u64,
+    evicted: u64,
+}
+
+/// What to do with requests that never complete within the timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum TimeoutPolicy {
+    /// Only count them, leaving the recorded distribution untouched.
+    #[default]
+    Drop,
+    /// Count them and record a sample equal to the timeout, so tail percentiles reflect
+    /// hung requests instead of ignoring them.
+    RecordTimeout,
+}
+
+impl<K: Hash + Eq + Clone> PairedTracker<K> {
+    /// Creates a tracker recording durations into `buffer`.
+    pub fn new(buffer: TimeBasedRingBuffer, max_pending: usize, timeout: u64) -> Self {
+        PairedTracker {
+            buffer,
+            pending: HashMap::new(),
+            order: VecDeque::new(),
+            max_pending,
+            timeout,
+            policy: TimeoutPolicy::default(),
+            timed_out: 0,
+            evicted: 0,
+        }
+    }
+
+    /// Sets how timed out requests are handled.
+    pub fn with_timeout_policy(mut self, policy: TimeoutPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Marks the start of request `id`. Starting an id that is already pending restarts it.
+    pub fn start(&mut self, id: K, timestamp: u64) -> Result<(), &'static str> {
+        self.expire(timestamp)?;
+        if self.max_pending == 0 {
+            self.evicted += 1;
+            return Ok(());
+        }
+        if !self.pending.contains_key(&id) && self.pending.len() >= self.max_pending {
+            self.evict_oldest();
+        }
+        self.pending.insert(id.clone(), timestamp);
+        self.order.push_back((timestamp, id));
+        if self.order.len() > 2 * self.max_pending {
+            let pending = &self.pending;
+            self.order.retain(|(ts, id)| pending.get(id) == Some(ts));
+        }
+        Ok(())
+    }
+
+    /// Marks the end of request `id` and records its duration. Returns the duration, or
+    /// `None` if the id was never started or has already been dropped.
+    pub fn end(&mut self, id: &K, timestamp: u64) -> Result<Option<u64>, &'static str> {
+        self.expire(timestamp)?;
+        let Some(started) = self.pending.remove(id) else {
+            return Ok(None);
+        };
+        let duration = timestamp.saturating_sub(started);
+        self.buffer.insert(duration, timestamp)?;
+        Ok(Some(duration))
+    }
+
+    /// Times out requests that started `timeout` or more before `now`.
+    pub fn expire(&mut self, now: u64) -> Result<(), &'static str> {
+        while let Some(&(started, ref id)) = self.order.front() {
+            let deadline = started.saturating_add(self.timeout);
+            if deadline > now {
+                break;
+            }
+            if self.pending.get(id) == Some(&started) {
+                self.pending.remove(id);
+                self.timed_out += 1;
+                if self.policy == TimeoutPolicy::RecordTimeout {
+                    self.buffer.insert(self.timeout, deadline)?;
+                }
+            }
+            self.order.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of requests that timed out.
+    pub fn timed_out(&self) -> u64 {
+        self.timed_out
+    }
+
+    /// Returns the number of requests dropped because too many were pending.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Returns the number of requests waiting for their end event.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the ring buffer the durations are recorded into.
+    pub fn buffer(&self) -> &TimeBasedRingBuffer {
+        &self.buffer
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((started, id)) = self.order.pop_front() {
+            if self.pending.get(&id) == Some(&started) {
+                self.pending.remove(&id);
+                self.evicted += 1;
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_paired_durations() {
+        let buffer = TimeBasedRingBuffer::new(4, 100, 0, 1000);
+        let mut tracker = PairedTracker::new(buffer, 2, 50);
+        tracker.start("a", 10).unwrap();
+        tracker.start("b", 12).unwrap();
+        assert_eq!(tracker.end(&"a", 25).unwrap(), Some(15));
+        assert_eq!(tracker.end(&"a", 26).unwrap(), None);
+        // A third pending request evicts the oldest one
+        tracker.start("c", 30).unwrap();
+        tracker.start("d", 31).unwrap();
+        assert_eq!(tracker.pending_len(), 2);
+        assert_eq!(tracker.end(&"b", 32).unwrap(), None);
+        // Requests pending longer than the timeout are dropped
+        assert_eq!(tracker.end(&"c", 80).unwrap(), None);
+        assert_eq!(tracker.end(&"d", 80).unwrap(), Some(49));
+        assert_eq!(tracker.pending_len(), 0);
+        assert_eq!(tracker.buffer().estimate_quantile(0.0).unwrap(), 15);
+        assert_eq!(tracker.buffer().estimate_quantile(1.0).unwrap(), 49);
+        assert_eq!(tracker.evicted(), 1);
+        assert_eq!(tracker.timed_out(), 1);
+    }
+    #[test]
+    fn test_record_timeout_policy() {
+        let buffer = TimeBasedRingBuffer::new(10, 100, 0, 1000);
+        let mut tracker =
+            PairedTracker::new(buffer, 10, 500).with_timeout_policy(TimeoutPolicy::RecordTimeout);
+        for id in 0..4 {
+            tracker.start(id, 0).unwrap();
+        }
+        assert_eq!(tracker.end(&0, 10).unwrap(), Some(10));
+        tracker.expire(499).unwrap();
+        assert_eq!(tracker.timed_out(), 0);
+        tracker.expire(500).unwrap();
+        assert_eq!(tracker.timed_out(), 3);
+        assert_eq!(tracker.pending_len(), 0);
+        assert_eq!(tracker.buffer().estimate_quantile(0.0).unwrap(), 10);
+        assert_eq!(tracker.buffer().estimate_quantile(0.5).unwrap(), 500);
+    }
+}
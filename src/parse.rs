@@ -0,0 +1,206 @@
+use std::fmt;
+
+/// Why a quantile expression was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// Nothing between two commas, or an empty expression.
+    Empty,
+    /// Not a number, a `p`-prefixed percentile or a known alias.
+    InvalidNumber,
+    /// A fraction above 1 or a percentile above 100.
+    OutOfRange,
+}
+
+/// A rejected quantile expression, pointing at the offending item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// Byte offset of the item in the input.
+    pub offset: usize,
+    /// The item as written, without surrounding whitespace.
+    pub item: String,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            ParseErrorKind::Empty => "empty quantile",
+            ParseErrorKind::InvalidNumber => "expected a fraction, a percentile or an alias",
+            ParseErrorKind::OutOfRange => "quantile outside 0..=1 (or p0..=p100)",
+        };
+        write!(f, "{reason} at offset {}: {:?}", self.offset, self.item)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a comma-separated list of quantiles, such as `"p50, p99.9"`, `"0.5,0.999"` or
+/// `"50,95,99"`.
+///
+/// Each item is one of:
+/// - a percentile prefixed with `p`, e.g. `p99.9`;
+/// - a number with a decimal point no greater than 1, read as a fraction, e.g. `0.999`;
+/// - any other number, read as a percentile, e.g. `99` or `99.9`;
+/// - `min`, `median` or `max`.
+///
+/// Percentiles are converted by moving the decimal point, so `p99.9` is exactly the same
+/// value as `0.999`. Exponents, signs, `inf` and `NaN` are rejected.
+pub fn parse_quantiles(input: &str) -> Result<Vec<f64>, ParseError> {
+    let mut offset = 0;
+    let mut fractions = Vec::new();
+    for raw in input.split(',') {
+        let trimmed = raw.trim_start();
+        let start = offset + raw.len() - trimmed.len();
+        fractions.push(parse_item(trimmed.trim_end(), start)?);
+        offset += raw.len() + 1;
+    }
+    Ok(fractions)
+}
+
+/// Parses a single quantile, with the same syntax as one item of [`parse_quantiles`].
+pub fn parse_quantile(input: &str) -> Result<f64, ParseError> {
+    let trimmed = input.trim_start();
+    parse_item(trimmed.trim_end(), input.len() - trimmed.len())
+}
+
+fn parse_item(item: &str, offset: usize) -> Result<f64, ParseError> {
+    let error = |kind| ParseError {
+        offset,
+        item: item.to_string(),
+        kind,
+    };
+    if item.is_empty() {
+        return Err(error(ParseErrorKind::Empty));
+    }
+    match item.to_ascii_lowercase().as_str() {
+        "min" => return Ok(0.0),
+        "median" => return Ok(0.5),
+        "max" => return Ok(1.0),
+        _ => {}
+    }
+    let (percent, number) = match item.strip_prefix(['p', 'P']) {
+        Some(rest) => (true, rest),
+        None => (false, item),
+    };
+    let (integer, decimals) = number.split_once('.').unwrap_or((number, ""));
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if integer.len() + decimals.len() == 0 || !digits(integer) || !digits(decimals) {
+        return Err(error(ParseErrorKind::InvalidNumber));
+    }
+    let as_fraction = !percent && number.contains('.');
+    let text = if as_fraction {
+        format!("{integer}.{decimals}")
+    } else {
+        percent_to_fraction(integer, decimals)
+    };
+    let value: f64 = text
+        .parse()
+        .map_err(|_| error(ParseErrorKind::InvalidNumber))?;
+    if as_fraction && value > 1.0 {
+        // A bare number like 99.9 is a percentile
+        return parse_item(&format!("p{item}"), offset).map_err(|e| ParseError {
+            item: item.to_string(),
+            ..e
+        });
+    }
+    if !(0.0..=1.0).contains(&value) {
+        return Err(error(ParseErrorKind::OutOfRange));
+    }
+    Ok(value)
+}
+
+/// Divides the decimal `integer.decimals` by 100 by moving its decimal point.
+fn percent_to_fraction(integer: &str, decimals: &str) -> String {
+    let digits = format!("{integer}{decimals}");
+    match integer.len().checked_sub(2) {
+        Some(point) if point > 0 => format!("{}.{}", &digits[..point], &digits[point..]),
+        Some(_) => format!("0.{digits}"),
+        None => format!("0.{}{digits}", "0".repeat(2 - integer.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::percentile_label;
+
+    /// Small deterministic generator, so the property tests are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0 >> 33
+        }
+    }
+
+    #[test]
+    fn test_parse_quantiles() {
+        assert_eq!(parse_quantiles("p99.9").unwrap(), vec![0.999]);
+        assert_eq!(parse_quantiles("0.999").unwrap(), vec![0.999]);
+        assert_eq!(parse_quantiles("99.9").unwrap(), vec![0.999]);
+        assert_eq!(parse_quantiles("50,95,99").unwrap(), vec![0.5, 0.95, 0.99]);
+        assert_eq!(
+            parse_quantiles(" p50 , P0.1,median,max, 1.0, 100 ").unwrap(),
+            vec![0.5, 0.001, 0.5, 1.0, 1.0, 1.0]
+        );
+        assert_eq!(parse_quantile(" 5 ").unwrap(), 0.05);
+        let error = parse_quantiles("p50, ,p99").unwrap_err();
+        assert_eq!((error.offset, error.kind), (5, ParseErrorKind::Empty));
+        let error = parse_quantiles("p50,1e-3").unwrap_err();
+        assert_eq!(error.offset, 4);
+        assert_eq!(error.item, "1e-3");
+        assert_eq!(error.kind, ParseErrorKind::InvalidNumber);
+        assert_eq!(
+            error.to_string(),
+            "expected a fraction, a percentile or an alias at offset 4: \"1e-3\""
+        );
+        for bad in [
+            "nan", "inf", "-0.5", "+5", "p", ".", "p9.9.9", "50%", "0x10",
+        ] {
+            assert_eq!(
+                parse_quantile(bad).unwrap_err().kind,
+                ParseErrorKind::InvalidNumber,
+                "{bad}"
+            );
+        }
+        for bad in ["p100.1", "101", "250.5"] {
+            assert_eq!(
+                parse_quantile(bad).unwrap_err().kind,
+                ParseErrorKind::OutOfRange
+            );
+        }
+    }
+
+    #[test]
+    fn test_labels_round_trip() {
+        let mut lcg = Lcg(7);
+        for _ in 0..10_000 {
+            let fraction = (lcg.next() % 1_000_001) as f64 / 1_000_000.0;
+            let label = percentile_label(fraction);
+            assert_eq!(parse_quantile(&label).unwrap(), fraction, "{label}");
+        }
+    }
+
+    #[test]
+    fn test_arbitrary_input_never_panics() {
+        let alphabet = b"pP0123456789.,, eE-+%nax";
+        let mut lcg = Lcg(11);
+        for _ in 0..20_000 {
+            let len = (lcg.next() % 12) as usize;
+            let input: String = (0..len)
+                .map(|_| alphabet[(lcg.next() as usize) % alphabet.len()] as char)
+                .collect();
+            match parse_quantiles(&input) {
+                Ok(fractions) => {
+                    assert!(fractions.iter().all(|f| (0.0..=1.0).contains(f)), "{input}")
+                }
+                Err(error) => assert!(error.offset <= input.len(), "{input}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,507 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::fraction::{IntoFraction, checked};
+use crate::registry::{QuantileRegistry, QuantileRegistryBuilder, matches_pattern};
+use crate::series::{Direction, Excursion};
+
+type Clock = fn() -> u64;
+
+type SinkFn = dyn FnMut(&Tick, &QuantileRegistry) + Send;
+
+struct Sink(Box<SinkFn>);
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sink")
+    }
+}
+
+#[derive(Debug)]
+struct AlertRule {
+    pattern: String,
+    fraction: Result<f64, &'static str>,
+    threshold: u64,
+}
+
+#[derive(Debug)]
+enum Message {
+    Record {
+        key: String,
+        value: u64,
+        timestamp: u64,
+    },
+    Shutdown,
+}
+
+/// A series whose quantile is above an alert rule's threshold at tick time.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Alert {
+    pub key: String,
+    pub fraction: f64,
+    pub threshold: u64,
+    /// The ongoing excursion above the threshold.
+    pub excursion: Excursion,
+}
+
+/// What the pipeline computed at one evaluation, passed to every sink.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Tick {
+    /// Clock reading at evaluation time.
+    pub timestamp: u64,
+    pub fractions: Vec<f64>,
+    /// Every series with data, sorted by key, with its quantiles in `fractions` order.
+    pub summaries: Vec<(String, Vec<u64>)>,
+    pub alerts: Vec<Alert>,
+    /// Values rejected by the registry since the previous tick, e.g. out of range.
+    pub dropped: usize,
+    /// Values refused since the previous tick because the queue to the pipeline's
+    /// thread was full, which [`PipelineRecorder::record`] also reports as an error.
+    pub queue_full: usize,
+    /// True for the final tick run by [`Pipeline::shutdown`].
+    pub last: bool,
+}
+
+/// Builds a [`Pipeline`] from a registry, the quantiles to derive, alert rules and sinks.
+#[derive(Debug)]
+pub struct PipelineBuilder {
+    registry: QuantileRegistryBuilder,
+    fractions: Result<Vec<f64>, &'static str>,
+    alerts: Vec<AlertRule>,
+    sinks: Vec<Sink>,
+    interval: Duration,
+    clock: Clock,
+    queue_capacity: usize,
+}
+
+impl PipelineBuilder {
+    /// Starts a pipeline recording into the registry `registry` builds, which sets the
+    /// windowing of every series. Ticks every 10 seconds, timestamps values in seconds
+    /// since the Unix epoch and queues up to 65536 values unless told otherwise.
+    pub fn new(registry: QuantileRegistryBuilder) -> Self {
+        PipelineBuilder {
+            registry,
+            fractions: Ok(Vec::new()),
+            alerts: Vec::new(),
+            sinks: Vec::new(),
+            interval: Duration::from_secs(10),
+            clock: unix_seconds,
+            queue_capacity: 1 << 16,
+        }
+    }
+
+    /// Computes these quantiles of every series at each tick.
+    pub fn fractions(mut self, fractions: &[impl IntoFraction]) -> Self {
+        self.fractions = checked(fractions);
+        self
+    }
+
+    /// Raises an [`Alert`] at each tick for every series matching `pattern` (see
+    /// [`QuantileRegistryBuilder::pattern`]) whose quantile at `fraction` is in an
+    /// ongoing excursion above `threshold`, as reported by
+    /// [`crossings`](crate::TimeBasedRingBuffer::crossings).
+    pub fn alert(mut self, pattern: &str, fraction: impl IntoFraction, threshold: u64) -> Self {
+        self.alerts.push(AlertRule {
+            pattern: pattern.to_string(),
+            fraction: fraction.into_fraction().map(|f| f.get()),
+            threshold,
+        });
+        self
+    }
+
+    /// Calls `sink` with every tick and the registry it was computed from, e.g. to log
+    /// the summaries, page on alerts or write
+    /// [`prometheus_summary`](QuantileRegistry::prometheus_summary) to a file.
+    pub fn sink<F>(mut self, sink: F) -> Self
+    where
+        F: FnMut(&Tick, &QuantileRegistry) + Send + 'static,
+    {
+        self.sinks.push(Sink(Box::new(sink)));
+        self
+    }
+
+    /// Sets how often ticks run.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the clock timestamping recorded values, e.g. one returning milliseconds to
+    /// match the series' window durations.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sets how many values can wait for the pipeline's thread. Once that many are
+    /// queued, recording fails until it catches up, instead of growing memory without
+    /// bound.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Starts the pipeline on its own thread, which owns the registry. Fails if a
+    /// fraction is invalid, or the interval or queue capacity is zero.
+    pub fn spawn(self) -> Result<Pipeline, &'static str> {
+        let fractions = self.fractions?;
+        let mut alerts = Vec::with_capacity(self.alerts.len());
+        for rule in self.alerts {
+            alerts.push((rule.pattern, rule.fraction?, rule.threshold));
+        }
+        if self.interval.is_zero() {
+            return Err("Interval must be greater than zero");
+        }
+        if self.queue_capacity == 0 {
+            return Err("Queue capacity must be greater than zero");
+        }
+        let queue_full = Arc::new(AtomicUsize::new(0));
+        let mut worker = Worker {
+            registry: self.registry.build(),
+            fractions,
+            alerts,
+            sinks: self.sinks,
+            clock: self.clock,
+            dropped: 0,
+            queue_full: Arc::clone(&queue_full),
+        };
+        let (sender, receiver) = mpsc::sync_channel(self.queue_capacity);
+        let interval = self.interval;
+        let thread = thread::spawn(move || {
+            let mut next_tick = Instant::now() + interval;
+            loop {
+                let timeout = next_tick.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(timeout) {
+                    Ok(Message::Record {
+                        key,
+                        value,
+                        timestamp,
+                    }) => {
+                        if worker.registry.record(&key, value, timestamp).is_err() {
+                            worker.dropped += 1;
+                        }
+                    }
+                    Ok(Message::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+                // Checked after every message too, so a steady stream can't starve ticks
+                if Instant::now() >= next_tick {
+                    worker.tick(false);
+                    next_tick += interval;
+                }
+            }
+            worker.tick(true);
+            worker.registry
+        });
+        Ok(Pipeline {
+            recorder: PipelineRecorder {
+                sender,
+                clock: self.clock,
+                queue_full,
+            },
+            thread: Some(thread),
+        })
+    }
+}
+
+/// A recorder, its windows, derived quantiles, alert rules and sinks wired into one
+/// owned object, running on a background thread from [`spawn`](PipelineBuilder::spawn)
+/// until [`shutdown`](Self::shutdown) or drop. Dropping it also processes the values
+/// already queued and runs a last tick, then waits for the thread to finish.
+#[derive(Debug)]
+pub struct Pipeline {
+    recorder: PipelineRecorder,
+    /// Taken by the first of `shutdown` and drop.
+    thread: Option<JoinHandle<QuantileRegistry>>,
+}
+
+impl Pipeline {
+    /// Returns a builder. See [`PipelineBuilder::new`].
+    pub fn builder(registry: QuantileRegistryBuilder) -> PipelineBuilder {
+        PipelineBuilder::new(registry)
+    }
+
+    /// Records `value` for the series `key` at the current time. Fails if the queue is
+    /// full, see [`PipelineBuilder::queue_capacity`].
+    pub fn record(&self, key: &str, value: u64) -> Result<(), &'static str> {
+        self.recorder.record(key, value)
+    }
+
+    /// Returns a handle that records into this pipeline from other threads.
+    pub fn recorder(&self) -> PipelineRecorder {
+        self.recorder.clone()
+    }
+
+    /// Stops the pipeline after the values already sent, runs a last tick and returns
+    /// the registry for a final export. Values sent from recorders afterwards fail.
+    pub fn shutdown(mut self) -> QuantileRegistry {
+        match self.stop() {
+            Some(Ok(registry)) => registry,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => unreachable!("only stopped by shutdown or drop"),
+        }
+    }
+
+    /// Tells the worker to stop after the queued values and waits for it.
+    fn stop(&mut self) -> Option<thread::Result<QuantileRegistry>> {
+        let thread = self.thread.take()?;
+        // The worker only stops on this message, so it is still receiving. Blocking
+        // here waits for room behind the queued values rather than dropping it.
+        let _ = self.recorder.sender.send(Message::Shutdown);
+        Some(thread.join())
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        if let Some(Err(panic)) = self.stop()
+            && !thread::panicking()
+        {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Records into a [`Pipeline`] from any thread. Values are handed to the pipeline's
+/// thread, so registry errors such as out-of-range values are counted in
+/// [`Tick::dropped`] rather than returned. A full queue is returned as an error, and
+/// counted in [`Tick::queue_full`].
+#[derive(Debug, Clone)]
+pub struct PipelineRecorder {
+    sender: SyncSender<Message>,
+    clock: Clock,
+    queue_full: Arc<AtomicUsize>,
+}
+
+impl PipelineRecorder {
+    /// Records `value` for the series `key` at the current time.
+    pub fn record(&self, key: &str, value: u64) -> Result<(), &'static str> {
+        self.record_at(key, value, (self.clock)())
+    }
+
+    /// Records `value` for the series `key` at `timestamp`.
+    pub fn record_at(&self, key: &str, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        let message = Message::Record {
+            key: key.to_string(),
+            value,
+            timestamp,
+        };
+        match self.sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.queue_full.fetch_add(1, Ordering::Relaxed);
+                Err("Pipeline queue is full")
+            }
+            Err(TrySendError::Disconnected(_)) => Err("Pipeline has shut down"),
+        }
+    }
+
+    /// Returns a handle recording into the series `key`, for passing to code that takes
+    /// a [`Record`](crate::Record).
+    pub fn series(&self, key: &str) -> PipelineSeries {
+        PipelineSeries {
+            recorder: self.clone(),
+            key: key.to_string(),
+        }
+    }
+}
+
+/// One series of a [`Pipeline`], from [`PipelineRecorder::series`]. Untimed values are
+/// timestamped with the pipeline's clock.
+#[derive(Debug, Clone)]
+pub struct PipelineSeries {
+    pub(crate) recorder: PipelineRecorder,
+    pub(crate) key: String,
+}
+
+impl PipelineSeries {
+    /// Returns the key of the series.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+struct Worker {
+    registry: QuantileRegistry,
+    fractions: Vec<f64>,
+    alerts: Vec<(String, f64, u64)>,
+    sinks: Vec<Sink>,
+    clock: Clock,
+    dropped: usize,
+    queue_full: Arc<AtomicUsize>,
+}
+
+impl Worker {
+    fn tick(&mut self, last: bool) {
+        let mut keys: Vec<&str> = self.registry.keys().collect();
+        keys.sort_unstable();
+        let mut summaries = Vec::with_capacity(keys.len());
+        let mut alerts = Vec::new();
+        for key in keys {
+            let Some(buffer) = self.registry.get(key) else {
+                continue;
+            };
+            let values: Result<Vec<u64>, _> = self
+                .fractions
+                .iter()
+                .map(|&f| buffer.estimate_quantile(f))
+                .collect();
+            if let Ok(values) = values
+                && !values.is_empty()
+            {
+                summaries.push((key.to_string(), values));
+            }
+            for (pattern, fraction, threshold) in &self.alerts {
+                if !matches_pattern(pattern, key) {
+                    continue;
+                }
+                let excursion = buffer
+                    .crossings(*fraction, *threshold, Direction::Above)
+                    .ok()
+                    .and_then(|mut excursions| excursions.pop())
+                    .filter(|excursion| excursion.ongoing);
+                if let Some(excursion) = excursion {
+                    alerts.push(Alert {
+                        key: key.to_string(),
+                        fraction: *fraction,
+                        threshold: *threshold,
+                        excursion,
+                    });
+                }
+            }
+        }
+        let tick = Tick {
+            timestamp: (self.clock)(),
+            fractions: self.fractions.clone(),
+            summaries,
+            alerts,
+            dropped: std::mem::take(&mut self.dropped),
+            queue_full: self.queue_full.swap(0, Ordering::Relaxed),
+            last,
+        };
+        for Sink(sink) in &mut self.sinks {
+            sink(&tick, &self.registry);
+        }
+    }
+}
+
+/// Seconds since the Unix epoch, or zero if the system clock is before it.
+pub(crate) fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    static NOW: AtomicU64 = AtomicU64::new(0);
+    fn now() -> u64 {
+        NOW.load(Ordering::SeqCst)
+    }
+    #[test]
+    fn test_pipeline() {
+        let config = SeriesConfig {
+            capacity: 6,
+            duration: 10,
+            start: 0,
+            end: 1000,
+        };
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&ticks);
+        let pipeline = Pipeline::builder(QuantileRegistry::builder(config))
+            .fractions(&[0.5, 1.0])
+            .alert("/api/*", 0.99, 500)
+            .sink(move |tick: &Tick, registry: &QuantileRegistry| {
+                assert_eq!(registry.len(), tick.summaries.len());
+                sink.lock().unwrap().push(tick.clone());
+            })
+            .interval(Duration::from_millis(5))
+            .clock(now)
+            .spawn()
+            .unwrap();
+        let recorder = pipeline.recorder();
+        let worker = thread::spawn(move || {
+            for v in 1..=10 {
+                recorder.record("/api/users", v * 10).unwrap();
+                recorder.record("/health", 1).unwrap();
+            }
+        });
+        worker.join().unwrap();
+        NOW.store(20, Ordering::SeqCst);
+        pipeline.record("/api/users", 900).unwrap();
+        pipeline.record("/api/users", 5000).unwrap();
+        let registry = pipeline.shutdown();
+        assert_eq!(registry.len(), 2);
+
+        let ticks = ticks.lock().unwrap();
+        let last = ticks.last().unwrap();
+        assert!(last.last);
+        assert_eq!(
+            last.summaries,
+            vec![
+                ("/api/users".to_string(), vec![60, 900]),
+                ("/health".to_string(), vec![1, 1]),
+            ]
+        );
+        assert_eq!(last.alerts.len(), 1);
+        assert_eq!(last.alerts[0].key, "/api/users");
+        assert_eq!(last.alerts[0].excursion.start, 20);
+        assert_eq!(ticks.iter().map(|t| t.dropped).sum::<usize>(), 1);
+
+        let invalid = Pipeline::builder(QuantileRegistry::builder(config)).alert("*", 1.5, 1);
+        assert!(invalid.spawn().is_err());
+        let invalid = Pipeline::builder(QuantileRegistry::builder(config)).interval(Duration::ZERO);
+        assert!(invalid.spawn().is_err());
+        let invalid = Pipeline::builder(QuantileRegistry::builder(config)).queue_capacity(0);
+        assert!(invalid.spawn().is_err());
+    }
+    #[test]
+    fn test_pipeline_backpressure_and_drop() {
+        let config = SeriesConfig {
+            capacity: 6,
+            duration: 10,
+            start: 0,
+            end: 1000,
+        };
+        let ticks = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&ticks);
+        // The sink holds up the worker until the test lets go of the gate
+        let gate = Arc::new(Mutex::new(()));
+        let held = gate.lock().unwrap();
+        let sink_gate = Arc::clone(&gate);
+        let (entered, in_sink) = mpsc::channel();
+        let pipeline = Pipeline::builder(QuantileRegistry::builder(config))
+            .fractions(&[1.0])
+            .sink(move |tick: &Tick, _: &QuantileRegistry| {
+                let _ = entered.send(());
+                let _gate = sink_gate.lock().unwrap();
+                sink.lock().unwrap().push(tick.clone());
+            })
+            .interval(Duration::from_millis(1))
+            .queue_capacity(1)
+            .clock(|| 0)
+            .spawn()
+            .unwrap();
+        in_sink.recv().unwrap();
+        pipeline.record("queued", 7).unwrap();
+        assert_eq!(pipeline.record("queued", 8), Err("Pipeline queue is full"));
+        drop(held);
+        // Dropping waits for the queued value and the last tick
+        drop(pipeline);
+        let ticks = ticks.lock().unwrap();
+        let last = ticks.last().unwrap();
+        assert!(last.last);
+        assert_eq!(last.summaries, vec![("queued".to_string(), vec![7])]);
+        assert_eq!(ticks.iter().map(|t| t.queue_full).sum::<usize>(), 1);
+    }
+}
@@ -0,0 +1,46 @@
+/// Where a snapshot came from, carried through merges so fleet aggregations can tell
+/// which hosts contributed to them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub hostname: String,
+    pub pid: u32,
+    pub crate_version: String,
+    /// Fingerprint of the snapshot configuration (range and window duration).
+    pub config_fingerprint: u64,
+    /// When the recording process started, in the same time unit as the windows.
+    pub start_time: u64,
+}
+
+impl Provenance {
+    /// Describes the current process. The hostname is taken from the `HOSTNAME`
+    /// environment variable or `/etc/hostname`. The config fingerprint is left at zero
+    /// and filled in by [`Snapshot::with_provenance`](crate::Snapshot::with_provenance).
+    pub fn current(start_time: u64) -> Self {
+        let hostname = std::env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "unknown".to_string());
+        Provenance {
+            hostname,
+            pid: std::process::id(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_fingerprint: 0,
+            start_time,
+        }
+    }
+}
+
+/// FNV-1a over the given words. Unlike `DefaultHasher`, the result is stable across
+/// Rust versions and platforms, so fingerprints can be compared between hosts.
+pub(crate) fn fingerprint(words: &[u64]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for word in words {
+        for byte in word.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
@@ -0,0 +1,286 @@
+//! Feeds histograms scraped from a proxy's stats endpoint into a [`QuantileRegistry`].
+//!
+//! Two text formats are understood:
+//!
+//! - Envoy's `/stats?histogram_buckets=cumulative`, with lines such as
+//!   `cluster.api.upstream_rq_time: B0.5(0,3) B1(1,7) B5(4,20)`, where each bucket is
+//!   `B<upper bound>(<interval count>,<cumulative count>)`.
+//! - Prometheus `_bucket` samples, as exported by NGINX (e.g. the VTS module) or by
+//!   Envoy's `/stats/prometheus`:
+//!   `nginx_vts_upstream_request_duration_seconds_bucket{upstream="api",le="0.05"} 12`.
+//!
+//! Bucket counts are cumulative since the proxy started, so a [`ProxyScraper`] keeps the
+//! previous scrape and records only the growth of each bucket since then.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use crate::registry::QuantileRegistry;
+
+/// Cumulative `(upper bound, count)` pairs in increasing bound order. `None` is `+Inf`.
+pub type Buckets = Vec<(Option<f64>, u64)>;
+
+/// Parses Envoy cumulative bucket lines and Prometheus `_bucket` samples, ignoring
+/// everything else. Histograms are keyed by their Envoy stat name, or by the Prometheus
+/// metric name without `_bucket` followed by its labels other than `le`.
+pub fn parse_histograms(text: &str) -> BTreeMap<String, Buckets> {
+    let mut scrape: BTreeMap<String, Buckets> = BTreeMap::new();
+    for line in text.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, buckets)) = parse_envoy(line) {
+            scrape.insert(name.to_string(), buckets);
+        } else if let Some((series, bound, count)) = parse_prometheus(line) {
+            scrape.entry(series).or_default().push((bound, count));
+        }
+    }
+    for buckets in scrape.values_mut() {
+        buckets.sort_by(|a, b| match (a.0, b.0) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        });
+    }
+    scrape
+}
+
+/// `cluster.api.upstream_rq_time: B0.5(0,2) B1(0,5)`
+fn parse_envoy(line: &str) -> Option<(&str, Buckets)> {
+    let (name, rest) = line.split_once(": ")?;
+    let mut buckets = Vec::new();
+    for bucket in rest.split_whitespace() {
+        let (bound, counts) = bucket.strip_prefix('B')?.split_once('(')?;
+        let (_, cumulative) = counts.strip_suffix(')')?.split_once(',')?;
+        buckets.push((Some(bound.parse().ok()?), cumulative.parse().ok()?));
+    }
+    (!buckets.is_empty()).then_some((name, buckets))
+}
+
+/// `name_bucket{upstream="api",le="0.05"} 12`
+fn parse_prometheus(line: &str) -> Option<(String, Option<f64>, u64)> {
+    let (sample, value) = line.rsplit_once(' ')?;
+    let (name, labels) = sample.split_once('{')?;
+    let name = name.strip_suffix("_bucket")?;
+    let mut bound = None;
+    let mut others = Vec::new();
+    for label in labels.strip_suffix('}')?.split(',') {
+        match label.strip_prefix("le=") {
+            Some(le) => bound = Some(le.trim_matches('"')),
+            None => others.push(label),
+        }
+    }
+    let bound = match bound? {
+        "+Inf" => None,
+        finite => Some(finite.parse().ok()?),
+    };
+    let count = value.parse::<f64>().ok()? as u64;
+    Some((format!("{name}{{{}}}", others.join(",")), bound, count))
+}
+
+/// What one [`ProxyScraper::ingest`] call recorded.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[non_exhaustive]
+pub struct Ingested {
+    /// Histograms recorded, including those that did not grow.
+    pub series: usize,
+    /// Values above the last finite bound, which have no bucket of their own and were
+    /// counted at that bound.
+    pub above_last_bound: usize,
+    /// Histograms the registry refused, with the reason.
+    pub rejected: Vec<(String, &'static str)>,
+}
+
+/// Turns successive scrapes of a proxy's stats into bucket inserts on a registry.
+#[derive(Debug)]
+pub struct ProxyScraper {
+    scale: f64,
+    previous: Option<BTreeMap<String, Buckets>>,
+}
+
+impl ProxyScraper {
+    /// Creates a scraper that multiplies bucket bounds by `scale` and rounds them up to
+    /// get values in the recording unit, e.g. 1 for Envoy's milliseconds or 1000 to record
+    /// NGINX's seconds as milliseconds. Fails unless `scale` is finite and positive.
+    pub fn new(scale: f64) -> Result<Self, &'static str> {
+        if !(scale.is_finite() && scale > 0.0) {
+            return Err("Scale must be finite and positive");
+        }
+        Ok(ProxyScraper {
+            scale,
+            previous: None,
+        })
+    }
+
+    /// Records into `registry` at `timestamp` what each histogram in `text` gained since
+    /// the previous call. The first call only sets the baseline and records nothing, and a
+    /// histogram seen for the first time is recorded from zero.
+    ///
+    /// Buckets whose scaled bounds round to the same value are combined. A series the
+    /// registry rejects, for instance because a bucket falls outside its range, is
+    /// reported in [`Ingested::rejected`] without stopping the others.
+    pub fn ingest(
+        &mut self,
+        text: &str,
+        registry: &mut QuantileRegistry,
+        timestamp: u64,
+    ) -> Result<Ingested, &'static str> {
+        let scrape = parse_histograms(text);
+        let Some(previous) = self.previous.replace(scrape) else {
+            return Ok(Ingested::default());
+        };
+        let scrape = self.previous.as_ref().expect("just replaced");
+        let mut ingested = Ingested::default();
+        for (name, buckets) in scrape {
+            let before = previous.get(name).map_or(&[][..], Vec::as_slice);
+            let (boundaries, counts, above) = interval_buckets(before, buckets, self.scale);
+            ingested.series += 1;
+            ingested.above_last_bound += above;
+            if let Err(e) = registry.record_buckets(name, &boundaries, &counts, timestamp) {
+                ingested.rejected.push((name.clone(), e));
+            }
+        }
+        Ok(ingested)
+    }
+}
+
+/// The growth of each bucket between two scrapes as non-cumulative counts, with bounds
+/// scaled and rounded up. Values above the last finite bound are counted in the last
+/// bucket, and their number is returned alongside.
+fn interval_buckets(
+    before: &[(Option<f64>, u64)],
+    after: &[(Option<f64>, u64)],
+    scale: f64,
+) -> (Vec<u64>, Vec<usize>, usize) {
+    let cumulative_before = |bound: Option<f64>| {
+        before
+            .iter()
+            .find(|(b, _)| *b == bound)
+            .map_or(0, |(_, count)| *count)
+    };
+    let mut boundaries: Vec<u64> = Vec::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut previous = 0;
+    let mut above = 0;
+    for &(bound, cumulative) in after {
+        // A counter going backwards means the proxy restarted; count from zero
+        let grown = cumulative.saturating_sub(cumulative_before(bound));
+        let in_bucket = grown.saturating_sub(previous) as usize;
+        previous = grown;
+        let Some(bound) = bound else {
+            above += in_bucket;
+            continue;
+        };
+        let scaled = (bound * scale).ceil() as u64;
+        match boundaries.last() {
+            Some(&last) if last >= scaled => *counts.last_mut().unwrap() += in_bucket,
+            _ => {
+                boundaries.push(scaled);
+                counts.push(in_bucket);
+            }
+        }
+    }
+    if let Some(last) = counts.last_mut() {
+        *last += above;
+    }
+    (boundaries, counts, above)
+}
+
+/// Fetches `path` from `address` with a plain HTTP/1.0 GET and returns the body.
+pub fn fetch(address: &str, path: &str) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(address)?;
+    write!(stream, "GET {path} HTTP/1.0\r\nHost: {address}\r\n\r\n")?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response
+        .split_once("\r\n\r\n")
+        .map_or(String::new(), |(_, body)| body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::SeriesConfig;
+    #[test]
+    fn test_parse_envoy() {
+        let text = "\
+cluster.api.upstream_rq_time: B0.5(1,3) B1(4,10) B5(45,90)
+cluster.api.upstream_cx_length_ms: No recorded values
+";
+        let scrape = parse_histograms(text);
+        assert_eq!(scrape.len(), 1);
+        assert_eq!(
+            scrape["cluster.api.upstream_rq_time"],
+            vec![(Some(0.5), 3), (Some(1.0), 10), (Some(5.0), 90)]
+        );
+    }
+    #[test]
+    fn test_parse_prometheus() {
+        let text = "\
+# TYPE rq histogram
+rq_bucket{upstream=\"api\",le=\"+Inf\"} 100
+rq_bucket{upstream=\"api\",le=\"0.5\"} 95
+rq_bucket{upstream=\"api\",le=\"0.005\"} 10
+rq_bucket{upstream=\"web\",le=\"0.005\"} 7
+rq_count{upstream=\"api\"} 100
+";
+        let scrape = parse_histograms(text);
+        assert_eq!(
+            scrape["rq{upstream=\"api\"}"],
+            vec![(Some(0.005), 10), (Some(0.5), 95), (None, 100)]
+        );
+        assert_eq!(scrape["rq{upstream=\"web\"}"], vec![(Some(0.005), 7)]);
+        assert_eq!(scrape.len(), 2);
+    }
+    #[test]
+    fn test_interval_buckets() {
+        let before = [
+            (Some(0.0015), 5),
+            (Some(0.002), 8),
+            (Some(1.0), 10),
+            (None, 10),
+        ];
+        let after = [
+            (Some(0.0015), 6),
+            (Some(0.002), 12),
+            (Some(1.0), 20),
+            (None, 23),
+        ];
+        // 0.0015 and 0.002 seconds both round up to 2 milliseconds
+        let (boundaries, counts, above) = interval_buckets(&before, &after, 1000.0);
+        assert_eq!(boundaries, vec![2, 1000]);
+        assert_eq!(counts, vec![4, 9]);
+        assert_eq!(above, 3);
+        // Counters going backwards after a restart record nothing
+        let (_, counts, _) = interval_buckets(&after, &before, 1000.0);
+        assert_eq!(counts, vec![0, 0]);
+    }
+    #[test]
+    fn test_ingest() {
+        let config = SeriesConfig {
+            capacity: 3,
+            duration: 10,
+            start: 0,
+            end: 1000,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        let mut scraper = ProxyScraper::new(1.0).unwrap();
+        let before = "rq: B10(0,0) B100(0,0)\nwide: B10(0,0)\n";
+        assert_eq!(
+            scraper.ingest(before, &mut registry, 0).unwrap(),
+            Ingested::default()
+        );
+        assert!(registry.is_empty());
+        let after = "rq: B10(10,10) B100(5,15)\nwide: B5000(1,1)\n";
+        let ingested = scraper.ingest(after, &mut registry, 5).unwrap();
+        assert_eq!(ingested.series, 2);
+        assert_eq!(ingested.rejected.len(), 1);
+        assert_eq!(ingested.rejected[0].0, "wide");
+        let rq = registry.get("rq").unwrap();
+        assert!(rq.estimate_quantile(0.5).unwrap() <= 10);
+        assert!(rq.estimate_quantile(0.9).unwrap() > 10);
+        assert_eq!(rq.estimate_quantile(1.0).unwrap(), 100);
+        assert!(ProxyScraper::new(0.0).is_err());
+        assert!(ProxyScraper::new(f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use crate::concurrent::ConcurrentRingBuffer;
+use crate::estimator::QuantileEstimator;
+use crate::faceted::TaggedRecorder;
+use crate::pipeline::PipelineSeries;
+use crate::registry::SeriesRecorder;
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::sharded::ShardedRingBuffer;
+use crate::staged::StageRecorder;
+
+/// Minimal recording interface implemented by every recorder, so middleware can accept
+/// any backend without depending on a concrete type.
+///
+/// The trait is sealed so methods can be added without breaking downstream crates.
+pub trait Record: sealed::Sealed {
+    /// Records a value at the recorder's current time.
+    fn record(&mut self, value: u64) -> Result<(), &'static str>;
+
+    /// Records a value observed at `timestamp`.
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::estimator::QuantileEstimator {}
+    impl Sealed for crate::ring_buffer::TimeBasedRingBuffer {}
+    impl Sealed for crate::concurrent::ConcurrentRingBuffer {}
+    impl Sealed for &crate::concurrent::ConcurrentRingBuffer {}
+    impl Sealed for crate::sharded::ShardedRingBuffer {}
+    impl Sealed for &crate::sharded::ShardedRingBuffer {}
+    impl Sealed for crate::registry::SeriesRecorder<'_> {}
+    impl Sealed for crate::pipeline::PipelineSeries {}
+    impl Sealed for crate::staged::StageRecorder<'_> {}
+    impl Sealed for crate::faceted::TaggedRecorder<'_> {}
+}
+
+/// A plain estimator has no notion of time, so timestamps are ignored.
+impl Record for QuantileEstimator {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.add_value(value)
+    }
+
+    fn record_at(&mut self, value: u64, _timestamp: u64) -> Result<(), &'static str> {
+        self.add_value(value)
+    }
+}
+
+/// Untimed values go into the current window, which requires at least one timed insert.
+impl Record for TimeBasedRingBuffer {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        let now = self
+            .current_window_start()
+            .ok_or("No timestamp recorded yet")?;
+        self.insert(value, now)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.insert(value, timestamp)
+    }
+}
+
+impl Record for &ConcurrentRingBuffer {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.insert_current(value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.insert(value, timestamp)
+    }
+}
+
+impl Record for ConcurrentRingBuffer {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.insert_current(value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.insert(value, timestamp)
+    }
+}
+
+/// Untimed values go into the picked shard's current window, or the latest shard's.
+impl Record for &ShardedRingBuffer {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.insert_current(value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.insert(value, timestamp)
+    }
+}
+
+impl Record for ShardedRingBuffer {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.insert_current(value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.insert(value, timestamp)
+    }
+}
+
+/// Untimed values go into the series' current window, which requires at least one timed
+/// insert.
+impl Record for SeriesRecorder<'_> {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        let now = self
+            .registry
+            .get(&self.key)
+            .and_then(TimeBasedRingBuffer::current_window_start)
+            .ok_or("No timestamp recorded yet")?;
+        self.registry.record(&self.key, value, now)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.registry.record(&self.key, value, timestamp)
+    }
+}
+
+impl Record for PipelineSeries {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.recorder.record(&self.key, value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.recorder.record_at(&self.key, value, timestamp)
+    }
+}
+
+impl Record for StageRecorder<'_> {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        self.buffer.record(value)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.buffer.insert(value, timestamp)
+    }
+}
+
+impl Record for TaggedRecorder<'_> {
+    fn record(&mut self, value: u64) -> Result<(), &'static str> {
+        let now = self
+            .recorder
+            .rollup()
+            .current_window_start()
+            .ok_or("No timestamp recorded yet")?;
+        self.recorder.record(value, now, self.tags)
+    }
+
+    fn record_at(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.recorder.record(value, timestamp, self.tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::faceted::FacetedRecorder;
+    use crate::pipeline::Pipeline;
+    use crate::registry::{QuantileRegistry, SeriesConfig};
+    use crate::sharded::ShardStrategy;
+    use crate::staged::StagedTracker;
+
+    fn record_all(recorder: &mut impl Record) -> Result<(), &'static str> {
+        recorder.record_at(5, 100)?;
+        recorder.record(7)
+    }
+
+    #[test]
+    fn test_record_backends() {
+        let mut estimator = QuantileEstimator::new(0, 10);
+        record_all(&mut estimator).unwrap();
+        assert_eq!(estimator.estimate_quantile(1.0).unwrap(), 7);
+
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, 10, 0, 10);
+        assert!(ring_buffer.record(1).is_err());
+        record_all(&mut ring_buffer).unwrap();
+        assert_eq!(ring_buffer.estimate_quantile(1.0).unwrap(), 7);
+
+        let concurrent = ConcurrentRingBuffer::new(2, 10, 0, 10);
+        record_all(&mut &concurrent).unwrap();
+        concurrent.refresh();
+        assert_eq!(concurrent.estimate_quantile(0.0).unwrap(), 5);
+        assert_eq!(concurrent.estimate_quantile(1.0).unwrap(), 7);
+
+        let sharded =
+            ShardedRingBuffer::new(2, 2, 10, 0, 10).with_strategy(ShardStrategy::RoundRobin);
+        assert!((&sharded).record(1).is_err());
+        record_all(&mut &sharded).unwrap();
+        assert_eq!(sharded.stats().inserts, vec![1, 1]);
+        assert_eq!(sharded.estimate_quantile(1.0).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_record_keyed_handles() {
+        let config = SeriesConfig {
+            capacity: 2,
+            duration: 10,
+            start: 0,
+            end: 10,
+        };
+        let mut registry = QuantileRegistry::builder(config).build();
+        assert!(registry.series("a").record(1).is_err());
+        let mut series = registry.series("a");
+        assert_eq!(series.key(), "a");
+        record_all(&mut series).unwrap();
+        assert_eq!(
+            registry.get("a").unwrap().estimate_quantile(1.0).unwrap(),
+            7
+        );
+
+        let pipeline = Pipeline::builder(QuantileRegistry::builder(config))
+            .clock(|| 100)
+            .spawn()
+            .unwrap();
+        record_all(&mut pipeline.recorder().series("b")).unwrap();
+        let registry = pipeline.shutdown();
+        assert_eq!(
+            registry.get("b").unwrap().estimate_quantile(1.0).unwrap(),
+            7
+        );
+
+        let mut staged = StagedTracker::new(&["parse", "render"], 2, 10, 0, 10);
+        assert!(staged.stage_recorder("db").is_err());
+        record_all(&mut staged.stage_recorder("render").unwrap()).unwrap();
+        let render = staged.stage("render").unwrap();
+        assert_eq!(render.estimate_quantile(1.0).unwrap(), 7);
+
+        let mut faceted = FacetedRecorder::new(&["region"], 2, 10, 0, 10);
+        record_all(&mut faceted.tagged(&[("region", "eu")])).unwrap();
+        assert!(faceted.tagged(&[("status", "500")]).record(1).is_err());
+        assert_eq!(faceted.rollup().estimate_quantile(1.0).unwrap(), 7);
+    }
+}
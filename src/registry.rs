@@ -0,0 +1,354 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use crate::audit::AuditEntry;
+use crate::memory::Degradation;
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+/// Ring buffer configuration for one series. See [`TimeBasedRingBuffer::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeriesConfig {
+    pub capacity: usize,
+    pub duration: u64,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl SeriesConfig {
+    pub(crate) fn ring_buffer(&self) -> TimeBasedRingBuffer {
+        TimeBasedRingBuffer::new(self.capacity, self.duration, self.start, self.end)
+    }
+}
+
+/// What to do with a key that doesn't name an existing series.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NewSeries {
+    /// Record into the series `key`, creating it with `config` if it doesn't exist.
+    /// `key` may differ from the one recorded with, e.g. to collapse URL paths into
+    /// route templates.
+    Create { key: String, config: SeriesConfig },
+    /// Drop the value.
+    Reject,
+}
+
+/// Hook deciding how to handle keys without a series. It receives the key and the
+/// configuration its patterns select.
+type HookFn = dyn Fn(&str, SeriesConfig) -> NewSeries + Send + Sync;
+
+struct NewSeriesHook(Box<HookFn>);
+
+impl fmt::Debug for NewSeriesHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NewSeriesHook")
+    }
+}
+
+/// Builds a [`QuantileRegistry`] with a default configuration and optional per-key
+/// pattern overrides.
+#[derive(Debug)]
+pub struct QuantileRegistryBuilder {
+    default: SeriesConfig,
+    patterns: Vec<(String, SeriesConfig)>,
+    hook: Option<NewSeriesHook>,
+    memory_cap: Option<usize>,
+}
+
+impl QuantileRegistryBuilder {
+    /// Starts a builder whose series use `default` unless a pattern matches.
+    pub fn new(default: SeriesConfig) -> Self {
+        QuantileRegistryBuilder {
+            default,
+            patterns: Vec::new(),
+            hook: None,
+            memory_cap: None,
+        }
+    }
+
+    /// Uses `config` for keys matching `pattern`, where `*` matches any run of characters
+    /// (e.g. `/api/*`). Patterns are tried in the order they were added.
+    pub fn pattern(mut self, pattern: &str, config: SeriesConfig) -> Self {
+        self.patterns.push((pattern.to_string(), config));
+        self
+    }
+
+    /// Calls `hook` whenever a value is recorded under a key that doesn't name an existing
+    /// series, letting it normalize the key, reject it, or pick its configuration.
+    pub fn on_new_series<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str, SeriesConfig) -> NewSeries + Send + Sync + 'static,
+    {
+        self.hook = Some(NewSeriesHook(Box::new(hook)));
+        self
+    }
+
+    /// Keeps the memory used by all series under `bytes`, degrading in the order
+    /// described by [`Degradation`] when a new series would exceed it.
+    pub fn memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> QuantileRegistry {
+        QuantileRegistry {
+            default: self.default,
+            patterns: self.patterns,
+            hook: self.hook,
+            series: HashMap::new(),
+            memory_cap: self.memory_cap,
+            retention_limit: None,
+            degradation: Degradation::None,
+            audit: VecDeque::new(),
+        }
+    }
+}
+
+/// A set of named ring buffers, created on first use with the configuration matching
+/// their key.
+#[derive(Debug)]
+pub struct QuantileRegistry {
+    default: SeriesConfig,
+    patterns: Vec<(String, SeriesConfig)>,
+    hook: Option<NewSeriesHook>,
+    pub(crate) series: HashMap<String, TimeBasedRingBuffer>,
+    pub(crate) memory_cap: Option<usize>,
+    /// Capacity new series are clamped to once retention has been shortened.
+    pub(crate) retention_limit: Option<usize>,
+    pub(crate) degradation: Degradation,
+    pub(crate) audit: VecDeque<AuditEntry>,
+}
+
+impl QuantileRegistry {
+    /// Returns a builder for a registry using `default` for every series.
+    pub fn builder(default: SeriesConfig) -> QuantileRegistryBuilder {
+        QuantileRegistryBuilder::new(default)
+    }
+
+    /// Records a value with a timestamp into the series `key`, creating it if needed.
+    pub fn record(&mut self, key: &str, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        self.record_with(key, timestamp, |buffer| buffer.insert(value, timestamp))
+    }
+
+    /// Records a histogram binned elsewhere into the series `key`, creating it if needed.
+    /// See [`TimeBasedRingBuffer::insert_buckets`].
+    pub fn record_buckets(
+        &mut self,
+        key: &str,
+        boundaries: &[u64],
+        counts: &[usize],
+        timestamp: u64,
+    ) -> Result<(), &'static str> {
+        self.record_with(key, timestamp, |buffer| {
+            buffer.insert_buckets(boundaries, counts, timestamp)
+        })
+    }
+
+    /// Runs `insert` on the series `key`, creating it first if needed.
+    fn record_with(
+        &mut self,
+        key: &str,
+        timestamp: u64,
+        insert: impl FnOnce(&mut TimeBasedRingBuffer) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        if self.series.contains_key(key) {
+            return self.insert_existing(key, timestamp, insert);
+        }
+        let config = self.config_for(key);
+        let (key, config) = match &self.hook {
+            None => (key.to_string(), config),
+            Some(NewSeriesHook(hook)) => match hook(key, config) {
+                NewSeries::Create { key, config } => (key, config),
+                NewSeries::Reject => return Err("Series rejected"),
+            },
+        };
+        if self.series.contains_key(&key) {
+            return self.insert_existing(&key, timestamp, insert);
+        }
+        let capacity = self
+            .retention_limit
+            .map_or(config.capacity, |limit| config.capacity.min(limit));
+        let mut buffer = SeriesConfig { capacity, ..config }.ring_buffer();
+        insert(&mut buffer)?;
+        self.series.insert(key.clone(), buffer);
+        self.enforce_memory_cap(&key, timestamp)
+    }
+
+    /// Inserts into an existing series. Inserts can grow a series, e.g. when a window turns
+    /// dense or a rotation hook annotates, so with a memory cap the cap is checked again
+    /// whenever the series' footprint grew.
+    fn insert_existing(
+        &mut self,
+        key: &str,
+        timestamp: u64,
+        insert: impl FnOnce(&mut TimeBasedRingBuffer) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        let buffer = self.series.get_mut(key).expect("checked by the caller");
+        if self.memory_cap.is_none() {
+            return insert(buffer);
+        }
+        let before = buffer.memory_usage();
+        insert(buffer)?;
+        if buffer.memory_usage() > before {
+            self.enforce_memory_cap(key, timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the configuration a series named `key` gets: the first matching pattern's,
+    /// or the default.
+    pub fn config_for(&self, key: &str) -> SeriesConfig {
+        self.patterns
+            .iter()
+            .find(|(pattern, _)| matches_pattern(pattern, key))
+            .map_or(self.default, |(_, config)| *config)
+    }
+
+    /// Returns the ring buffer of a series.
+    pub fn get(&self, key: &str) -> Option<&TimeBasedRingBuffer> {
+        self.series.get(key)
+    }
+
+    /// Returns a handle recording into the series `key`, created on first use as with
+    /// [`record`](Self::record), for passing to code that takes a [`Record`](crate::Record).
+    pub fn series(&mut self, key: &str) -> SeriesRecorder<'_> {
+        SeriesRecorder {
+            registry: self,
+            key: key.to_string(),
+        }
+    }
+
+    /// Returns the keys of all series, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.series.keys().map(String::as_str)
+    }
+
+    /// Returns the number of series.
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+}
+
+/// One series of a [`QuantileRegistry`], from [`QuantileRegistry::series`].
+#[derive(Debug)]
+pub struct SeriesRecorder<'a> {
+    pub(crate) registry: &'a mut QuantileRegistry,
+    pub(crate) key: String,
+}
+
+impl SeriesRecorder<'_> {
+    /// Returns the key of the series.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+/// Matches `key` against a pattern where `*` stands for any run of characters.
+pub(crate) fn matches_pattern(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("/api/*", "/api/users"));
+        assert!(matches_pattern("/api/*", "/api/"));
+        assert!(!matches_pattern("/api/*", "/health"));
+        assert!(matches_pattern(
+            "batch.*.duration",
+            "batch.nightly.duration"
+        ));
+        assert!(!matches_pattern("batch.*.duration", "batch.nightly.count"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("exact", "exact"));
+        assert!(!matches_pattern("exact", "exactly"));
+        assert!(!matches_pattern("a*bc*c", "abc"));
+    }
+    #[test]
+    fn test_per_pattern_defaults() {
+        let api = SeriesConfig {
+            capacity: 60,
+            duration: 1,
+            start: 0,
+            end: 10_000,
+        };
+        let batch = SeriesConfig {
+            capacity: 60,
+            duration: 60,
+            start: 0,
+            end: 3_600,
+        };
+        let default = SeriesConfig {
+            capacity: 10,
+            duration: 10,
+            start: 0,
+            end: 1000,
+        };
+        let mut registry = QuantileRegistry::builder(default)
+            .pattern("/api/*", api)
+            .pattern("batch/*", batch)
+            .build();
+        assert_eq!(registry.config_for("/api/users"), api);
+        assert_eq!(registry.config_for("batch/nightly"), batch);
+        assert_eq!(registry.config_for("other"), default);
+        registry.record("/api/users", 9_000, 5).unwrap();
+        registry.record("batch/nightly", 2_000, 5).unwrap();
+        assert!(registry.record("other", 2_000, 5).is_err());
+        assert_eq!(registry.len(), 2);
+        let users = registry.get("/api/users").unwrap();
+        assert_eq!(users.estimate_quantile(0.5).unwrap(), 9_000);
+    }
+    #[test]
+    fn test_new_series_hook() {
+        let default = SeriesConfig {
+            capacity: 10,
+            duration: 10,
+            start: 0,
+            end: 1000,
+        };
+        let mut registry = QuantileRegistry::builder(default)
+            .on_new_series(|key, config| {
+                if key.starts_with("/internal") {
+                    return NewSeries::Reject;
+                }
+                let template: Vec<&str> = key
+                    .split('/')
+                    .map(|s| if s.parse::<u64>().is_ok() { "{id}" } else { s })
+                    .collect();
+                NewSeries::Create {
+                    key: template.join("/"),
+                    config,
+                }
+            })
+            .build();
+        registry.record("/users/1", 10, 0).unwrap();
+        registry.record("/users/2", 20, 0).unwrap();
+        registry.record("/users/3/posts", 30, 0).unwrap();
+        assert!(registry.record("/internal/health", 1, 0).is_err());
+        let mut keys: Vec<&str> = registry.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["/users/{id}", "/users/{id}/posts"]);
+        let users = registry.get("/users/{id}").unwrap();
+        assert_eq!(users.estimate_quantile(1.0).unwrap(), 20);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::concurrent::ConcurrentRingBuffer;
+use crate::estimator::QuantileEstimator;
+use crate::fraction::IntoFraction;
+use crate::merge::select_quantile;
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// How a quantile estimate is derived from the bucket counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Interpolation {
+    /// The value of the bucket holding the nearest-rank sample.
+    NearestRank,
+}
+
+/// A quantile estimate together with the context it was computed from, all taken from
+/// the same state so callers don't have to combine several racy queries.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct QuantileReport {
+    pub fraction: f64,
+    pub estimate: u64,
+    /// Smallest and largest values the true quantile can have. They differ from the
+    /// estimate only for data whose values were interpolated inside coarser buckets, by
+    /// [`QuantileEstimator::from_buckets`] or
+    /// [`TimeBasedRingBuffer::insert_buckets`], or whose counts were rounded by
+    /// [`Snapshot::quantized`], including snapshots merged or resampled from those.
+    pub lower_bound: u64,
+    pub upper_bound: u64,
+    /// Number of values the estimate was computed from.
+    pub sample_count: usize,
+    /// Time range `[start, end)` covered by the windows that held values.
+    pub covered: (u64, u64),
+    /// Number of windows that held values.
+    pub window_count: usize,
+    pub interpolation: Interpolation,
+}
+
+impl TimeBasedRingBuffer {
+    /// Like [`estimate_quantile`](Self::estimate_quantile), but returns a
+    /// [`QuantileReport`] with the sample count, covered time range and window count.
+    pub fn report(&self, fraction: impl IntoFraction) -> Result<QuantileReport, &'static str> {
+        report(self.windows(), self.duration(), fraction)
+    }
+}
+
+impl Snapshot {
+    /// Returns a [`QuantileReport`] for all windows combined.
+    pub fn report(&self, fraction: impl IntoFraction) -> Result<QuantileReport, &'static str> {
+        report(
+            self.windows.iter().map(|(ts, w)| (*ts, w)),
+            self.duration,
+            fraction,
+        )
+    }
+}
+
+impl ConcurrentRingBuffer {
+    /// Returns a [`QuantileReport`] computed from the last published snapshot.
+    pub fn report(&self, fraction: impl IntoFraction) -> Result<QuantileReport, &'static str> {
+        self.snapshot().report(fraction)
+    }
+}
+
+fn report<'a>(
+    windows: impl Iterator<Item = (u64, &'a QuantileEstimator)>,
+    duration: u64,
+    fraction: impl IntoFraction,
+) -> Result<QuantileReport, &'static str> {
+    let fraction = fraction.into_fraction()?.get();
+    let used: Vec<(u64, &QuantileEstimator)> = windows.filter(|(_, w)| w.val_count > 0).collect();
+    let (Some(first), Some(last)) = (used.first(), used.last()) else {
+        return Err("No values added to any window");
+    };
+    let covered = (first.0, last.0.saturating_add(duration));
+    let windows: Vec<&QuantileEstimator> = used.iter().map(|(_, w)| *w).collect();
+    let estimate = select_quantile(windows.iter().copied(), fraction)?;
+    let (lower_bound, upper_bound) = bounds(&windows, fraction, estimate)?;
+    Ok(QuantileReport {
+        fraction,
+        estimate,
+        lower_bound,
+        upper_bound,
+        sample_count: used.iter().map(|(_, w)| w.val_count).sum(),
+        covered,
+        window_count: used.len(),
+        interpolation: Interpolation::NearestRank,
+    })
+}
+
+/// Returns the smallest and largest values the true quantile at `fraction` can have,
+/// given the `estimate` from `windows`.
+///
+/// Rounding each count by up to ε of itself moves the share of values below any point
+/// from `f` to at most `f(1−f)·2ε/(1−ε)` away, so the true quantile lies between the
+/// estimates at the fractions that far either side. A bound landing in a bucket spread
+/// from a coarser histogram then widens to the whole bucket, since the true value can be
+/// anywhere in it.
+pub(crate) fn bounds(
+    windows: &[&QuantileEstimator],
+    fraction: f64,
+    estimate: u64,
+) -> Result<(u64, u64), &'static str> {
+    let error = windows.iter().map(|w| w.count_error).fold(0.0, f64::max);
+    let (mut lower, mut upper) = (estimate, estimate);
+    if error > 0.0 {
+        let shift = if error < 1.0 {
+            fraction * (1.0 - fraction) * 2.0 * error / (1.0 - error)
+        } else {
+            1.0
+        };
+        lower = select_quantile(windows.iter().copied(), (fraction - shift).max(0.0))?;
+        upper = select_quantile(windows.iter().copied(), (fraction + shift).min(1.0))?;
+    }
+    for window in windows {
+        lower = lower.min(window.spread_around(lower).0);
+        upper = upper.max(window.spread_around(upper).1);
+    }
+    Ok((lower, upper))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_report() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(5, 10, 0, 100);
+        assert!(ring_buffer.report(0.5).is_err());
+        for (value, ts) in [(10, 12), (20, 15), (30, 31), (40, 45)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        let report = ring_buffer.report(0.5).unwrap();
+        assert_eq!(report.estimate, 20);
+        assert_eq!(report.fraction, 0.5);
+        assert_eq!((report.lower_bound, report.upper_bound), (20, 20));
+        assert_eq!(report.sample_count, 4);
+        assert_eq!(report.covered, (10, 50));
+        assert_eq!(report.window_count, 3);
+        assert_eq!(report.interpolation, Interpolation::NearestRank);
+        assert_eq!(ring_buffer.snapshot().report(0.5).unwrap(), report);
+        assert!(ring_buffer.report(1.5).is_err());
+    }
+    #[test]
+    fn test_report_bounds() {
+        // 10 values in 0..=10 and 80 spread over 11..=50
+        let mut buffer = TimeBasedRingBuffer::new(2, 10, 0, 100);
+        buffer
+            .insert_buckets(&[10, 50, 100], &[10, 80, 0], 5)
+            .unwrap();
+        let report = buffer.report(0.5).unwrap();
+        assert_eq!(report.estimate, 28);
+        assert_eq!((report.lower_bound, report.upper_bound), (11, 50));
+        // Exact values next to the spread ones keep exact bounds
+        buffer.insert(70, 6).unwrap();
+        let report = buffer.report(1.0).unwrap();
+        assert_eq!((report.lower_bound, report.upper_bound), (70, 70));
+
+        let mut exact = TimeBasedRingBuffer::new(2, 10, 0, 10_000);
+        for value in 1..=10_000 {
+            exact.insert(value, 5).unwrap();
+        }
+        let snapshot = exact.snapshot();
+        assert_eq!(snapshot.report(0.5).unwrap().lower_bound, 5000);
+        // Rounding to 2 digits leaves counts of one alone, but the bounds only know that
+        // any count may have moved by 5%, which shifts p50 by up to 2.6 points
+        let quantized = snapshot.quantized(2).report(0.5).unwrap();
+        assert_eq!(quantized.estimate, 5000);
+        assert_eq!((quantized.lower_bound, quantized.upper_bound), (4737, 5263));
+
+        // Resampling keeps the interpolated buckets of its sources
+        let resampled = buffer.snapshot().resample(5).unwrap().report(0.5).unwrap();
+        assert_eq!((resampled.lower_bound, resampled.upper_bound), (11, 50));
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::annotation::Annotation;
+use crate::audit::{self, AuditEntry, AuditEvent};
+use crate::estimator::{QuantileEstimator, RANK_BLOCK};
+use crate::fraction::IntoFraction;
+use crate::merge::{select_between, select_quantile};
+use crate::rotation::{FollowUp, RotationHook};
+use crate::snapshot::Snapshot;
+
+/// What [`TimeBasedRingBuffer::resize`] does with the oldest windows when shrinking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ShrinkPolicy {
+    /// Discard the windows that no longer fit.
+    #[default]
+    Drop,
+    /// Merge them into the oldest window that is kept, so no values are lost but that
+    /// window then spans more than one duration.
+    MergeIntoOldest,
+}
+
+/// A ring buffer that stores QuantileEstimator instances for sliding window quantile estimation.
+#[derive(Debug)]
+pub struct TimeBasedRingBuffer {
+    capacity: usize,
+    duration: u64,
+    pub(crate) windows: Vec<QuantileEstimator>,
+    pub(crate) current: usize,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    current_window_start: u64,
+    current_window_initialized: bool,
+    pub(crate) annotations: Vec<Annotation>,
+    pub(crate) audit: VecDeque<AuditEntry>,
+    pub(crate) rotation_hook: Option<RotationHook>,
+}
+
+impl TimeBasedRingBuffer {
+    /// Creates a new TimeBasedRingBuffer.
+    pub fn new(capacity: usize, duration: u64, start: u64, end: u64) -> Self {
+        let windows = vec![QuantileEstimator::new(start, end); capacity];
+        TimeBasedRingBuffer {
+            capacity,
+            duration,
+            windows,
+            current: 0,
+            start,
+            end,
+            current_window_start: 0,
+            current_window_initialized: false,
+            annotations: Vec::new(),
+            audit: VecDeque::new(),
+            rotation_hook: None,
+        }
+    }
+
+    /// Inserts a value with a timestamp into the appropriate window.
+    ///
+    /// Windows are aligned to multiples of the duration, starting at timestamp 0. Every
+    /// timestamp up to `u64::MAX` is accepted: the last window, whose end would overflow,
+    /// simply never rotates. Values may equal the range's `end`, which is inclusive.
+    pub fn insert(&mut self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        #[cfg(feature = "overhead")]
+        let _timer = crate::overhead::Timer::start(crate::overhead::Operation::Insert);
+        let follow_ups = self.rotate(timestamp)?;
+        let added = self.windows[self.current].add_value(value);
+        // Run after the value is stored, so a follow-up that rotates again can't move the
+        // current window past its timestamp
+        for follow_up in follow_ups {
+            follow_up(self);
+        }
+        added
+    }
+
+    /// Moves the clock to `timestamp`, rotating out the windows that ended before it.
+    pub(crate) fn advance(&mut self, timestamp: u64) -> Result<(), &'static str> {
+        for follow_up in self.rotate(timestamp)? {
+            follow_up(self);
+        }
+        Ok(())
+    }
+
+    /// Moves the clock like [`advance`](Self::advance), returning the follow-ups the
+    /// rotation hook scheduled for the caller to run.
+    fn rotate(&mut self, timestamp: u64) -> Result<Vec<FollowUp>, &'static str> {
+        if !self.current_window_initialized {
+            if self.duration == 0 {
+                return Err("Duration must be greater than zero");
+            }
+            if self.capacity == 0 {
+                return Err("Capacity must be greater than zero");
+            }
+            self.current_window_start = timestamp - (timestamp % self.duration);
+            self.current_window_initialized = true;
+        }
+        // Advance window(s) as needed, recycling the evicted windows' buckets
+        if self.would_rotate(timestamp) {
+            let follow_ups = self.notify_rotation(timestamp);
+            let steps = (timestamp - self.current_window_start) / self.duration;
+            let resets = steps.min(self.capacity as u64) as usize;
+            for offset in 1..=resets {
+                self.windows[(self.current + offset) % self.capacity].reset();
+            }
+            let capacity = self.capacity as u64;
+            self.current = ((self.current as u64 + steps % capacity) % capacity) as usize;
+            // Cannot overflow: the new start is at most `timestamp`.
+            self.current_window_start += steps * self.duration;
+            let oldest = self.oldest_window_start();
+            self.annotations.retain(|a| a.timestamp >= oldest);
+            return Ok(follow_ups);
+        }
+        Ok(Vec::new())
+    }
+
+    /// Changes the number of retained windows, keeping the data of the most recent ones.
+    ///
+    /// Growing adds empty windows before the oldest one. Shrinking removes the oldest
+    /// windows, dropping or merging their values as `policy` says, and prunes annotations
+    /// that fall before the new oldest window.
+    pub fn resize(&mut self, capacity: usize, policy: ShrinkPolicy) -> Result<(), &'static str> {
+        if capacity == 0 {
+            return Err("Capacity must be greater than zero");
+        }
+        // Rotate so the windows are stored oldest first and the current one is last.
+        if self.capacity > 0 {
+            self.windows.rotate_left((self.current + 1) % self.capacity);
+        }
+        if capacity > self.capacity {
+            let empty = QuantileEstimator::new(self.start, self.end);
+            let added = capacity - self.capacity;
+            self.windows.splice(0..0, std::iter::repeat_n(empty, added));
+        } else {
+            let removed: Vec<QuantileEstimator> =
+                self.windows.drain(..self.capacity - capacity).collect();
+            if policy == ShrinkPolicy::MergeIntoOldest {
+                for window in &removed {
+                    self.windows[0].merge(window)?;
+                }
+            }
+        }
+        let event = AuditEvent::Resized {
+            from: self.capacity,
+            to: capacity,
+            policy,
+        };
+        audit::push(&mut self.audit, self.current_window_start, event);
+        self.capacity = capacity;
+        self.current = capacity - 1;
+        let oldest = self.oldest_window_start();
+        self.annotations.retain(|a| a.timestamp >= oldest);
+        Ok(())
+    }
+
+    /// Returns the start of the oldest retained window, clamped at timestamp 0.
+    fn oldest_window_start(&self) -> u64 {
+        let span = (self.capacity as u64).saturating_sub(1);
+        self.current_window_start
+            .saturating_sub(span.saturating_mul(self.duration))
+    }
+
+    /// Returns the approximate number of bytes held by the ring buffer and its windows.
+    pub fn memory_usage(&self) -> usize {
+        size_of::<Self>()
+            + self
+                .windows
+                .iter()
+                .map(QuantileEstimator::memory_usage)
+                .sum::<usize>()
+            + self.annotations.capacity() * size_of::<Annotation>()
+    }
+
+    /// Returns the capacity, the number of windows retained.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the duration of each window.
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    /// Returns the start of the window currently being filled, or `None` before the first
+    /// insert.
+    pub fn current_window_start(&self) -> Option<u64> {
+        self.current_window_initialized
+            .then_some(self.current_window_start)
+    }
+
+    /// Returns true if inserting at `timestamp` would complete the current window.
+    /// The window whose end would overflow `u64` never completes.
+    pub(crate) fn would_rotate(&self, timestamp: u64) -> bool {
+        self.current_window_initialized
+            && self
+                .current_window_start
+                .checked_add(self.duration)
+                .is_some_and(|end| timestamp >= end)
+    }
+
+    /// Returns the quantile of all windows combined.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        #[cfg(feature = "overhead")]
+        let _timer = crate::overhead::Timer::start(crate::overhead::Operation::Query);
+        let fraction = fraction.into_fraction()?.get();
+        if self.windows.is_empty() {
+            return Err("No windows available in the ring buffer");
+        }
+        let total_val_count: usize = self.windows.iter().map(|w| w.val_count).sum();
+        if total_val_count == 0 {
+            return Err("No values added to any window");
+        }
+        select_quantile(&self.windows, fraction)
+    }
+
+    /// Returns the quantile over the time range `[from, to)`.
+    ///
+    /// Only the windows overlapping the range are visited. Windows partially inside it
+    /// contribute their counts scaled by the overlapping fraction of their duration,
+    /// assuming values arrived uniformly over the window.
+    pub fn estimate_quantile_between(
+        &self,
+        fraction: impl IntoFraction,
+        from: u64,
+        to: u64,
+    ) -> Result<u64, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        if from >= to {
+            return Err("Time range is empty");
+        }
+        select_between(
+            self.windows_between(from, to),
+            self.duration,
+            fraction,
+            from,
+            to,
+        )
+    }
+
+    /// Returns the retained windows, oldest first, paired with their start timestamps.
+    /// Nothing is returned before the first insert.
+    pub fn windows(&self) -> impl Iterator<Item = (u64, &QuantileEstimator)> {
+        let retained = if self.current_window_initialized {
+            self.capacity
+        } else {
+            0
+        };
+        self.windows_by_age(0..retained)
+    }
+
+    /// Returns the retained windows overlapping `[from, to)`, oldest first, working out
+    /// which windows those are from their ages instead of scanning the whole buffer.
+    pub(crate) fn windows_between(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> impl Iterator<Item = (u64, &QuantileEstimator)> {
+        let current_end = self.current_window_start.saturating_add(self.duration);
+        let ages = if !self.current_window_initialized || from >= current_end || from >= to {
+            0..0
+        } else {
+            // A window of age k covers [current_window_start - k * duration, + duration).
+            let youngest = if to > self.current_window_start {
+                0
+            } else {
+                (self.current_window_start - to) / self.duration + 1
+            };
+            let oldest = (current_end - from).div_ceil(self.duration);
+            let youngest = youngest.min(self.capacity as u64) as usize;
+            youngest..(oldest.min(self.capacity as u64) as usize)
+        };
+        self.windows_by_age(ages)
+    }
+
+    pub(crate) fn windows_by_age(
+        &self,
+        ages: Range<usize>,
+    ) -> impl Iterator<Item = (u64, &QuantileEstimator)> {
+        ages.rev().filter_map(move |age| {
+            let start = self
+                .current_window_start
+                .checked_sub(age as u64 * self.duration)?;
+            let slot = (self.current + self.capacity - age) % self.capacity;
+            Some((start, &self.windows[slot]))
+        })
+    }
+
+    /// Copies the retained windows into a [`Snapshot`] that can be queried, smoothed
+    /// and merged without holding on to the ring buffer.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            start: self.start,
+            end: self.end,
+            duration: self.duration,
+            windows: self.windows().map(|(ts, w)| (ts, w.clone())).collect(),
+            contributors: Vec::new(),
+            contributor_index: None,
+            annotations: self.annotations.clone(),
+            audit: self.audit.iter().cloned().collect(),
+        }
+    }
+
+    /// Returns the number of distinct values across all windows combined.
+    pub fn distinct_estimate(&self) -> usize {
+        let Some(first) = self.windows.first() else {
+            return 0;
+        };
+        let mut distinct = 0;
+        for block in 0..first.block_counts.len() {
+            if self.windows.iter().all(|w| w.block_counts[block] == 0) {
+                continue;
+            }
+            let block_start = block * RANK_BLOCK;
+            let block_end = (block_start + RANK_BLOCK).min(first.quantiles.len());
+            distinct += (block_start..block_end)
+                .filter(|&i| self.windows.iter().any(|w| w.quantiles[i] > 0))
+                .count();
+        }
+        distinct
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_time_based_ring_buffer() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        ring_buffer.insert(1, 0).unwrap();
+        ring_buffer.insert(2, 5).unwrap();
+        ring_buffer.insert(3, 5).unwrap();
+        assert_eq!(ring_buffer.current, 0);
+        ring_buffer.insert(3, 100).unwrap();
+        assert_eq!(ring_buffer.current, 1);
+    }
+    #[test]
+    fn test_rotation_recycles_windows() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        ring_buffer.insert(50, 0).unwrap();
+        let ptr = ring_buffer.windows[0].quantiles.as_ptr();
+        ring_buffer.insert(1, 10).unwrap();
+        ring_buffer.insert(1, 20).unwrap();
+        ring_buffer.insert(1, 30).unwrap();
+        assert_eq!(ring_buffer.current, 0);
+        assert_eq!(ring_buffer.windows[0].quantiles.as_ptr(), ptr);
+        assert_eq!(ring_buffer.windows[0].val_count, 1);
+        assert_eq!(ring_buffer.estimate_quantile(1.0).unwrap(), 1);
+        // A gap longer than the whole buffer clears every window once
+        ring_buffer.insert(7, 1000).unwrap();
+        assert_eq!(ring_buffer.current, 1);
+        assert_eq!(ring_buffer.current_window_start, 1000);
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 7);
+        assert_eq!(ring_buffer.estimate_quantile(1.0).unwrap(), 7);
+    }
+    #[test]
+    fn test_combined_quantile_across_merge_blocks() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 10, 0, 5000);
+        for (ts, value) in [(0, 10), (10, 1500), (20, 2600), (30, 4999), (30, 5000)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 10);
+        assert_eq!(ring_buffer.estimate_quantile(0.4).unwrap(), 1500);
+        assert_eq!(ring_buffer.estimate_quantile(0.6).unwrap(), 2600);
+        assert_eq!(ring_buffer.estimate_quantile(1.0).unwrap(), 5000);
+    }
+    #[test]
+    fn test_distinct_estimate_across_windows() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 5000);
+        assert_eq!(ring_buffer.distinct_estimate(), 0);
+        for (value, ts) in [(100, 0), (100, 10), (4000, 10), (100, 20), (2, 25)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        assert_eq!(ring_buffer.distinct_estimate(), 3);
+        ring_buffer.insert(100, 40).unwrap();
+        assert_eq!(ring_buffer.distinct_estimate(), 2);
+    }
+    #[test]
+    fn test_windows_and_snapshot() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        assert_eq!(ring_buffer.windows().count(), 0);
+        ring_buffer.insert(1, 12).unwrap();
+        let starts: Vec<u64> = ring_buffer.windows().map(|(ts, _)| ts).collect();
+        assert_eq!(starts, vec![0, 10]);
+        ring_buffer.insert(2, 25).unwrap();
+        ring_buffer.insert(3, 37).unwrap();
+        let starts: Vec<u64> = ring_buffer.windows().map(|(ts, _)| ts).collect();
+        assert_eq!(starts, vec![10, 20, 30]);
+        let snapshot = ring_buffer.snapshot();
+        assert_eq!(snapshot.windows().len(), 3);
+        assert_eq!(snapshot.windows()[2].1.estimate_quantile(0.5).unwrap(), 3);
+        assert_eq!(snapshot.estimate_quantile(0.0).unwrap(), 1);
+        assert_eq!(snapshot.combined().rank(2), 2);
+    }
+    #[test]
+    fn test_resize() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        for (value, ts) in [(1, 0), (2, 10), (3, 20), (4, 30)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        ring_buffer.resize(5, ShrinkPolicy::Drop).unwrap();
+        let starts: Vec<u64> = ring_buffer.windows().map(|(ts, _)| ts).collect();
+        assert_eq!(starts, vec![0, 10, 20, 30]);
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 2);
+        // The added windows fill up as time moves on
+        ring_buffer.insert(5, 40).unwrap();
+        ring_buffer.insert(6, 50).unwrap();
+        assert_eq!(ring_buffer.windows().count(), 5);
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 2);
+
+        ring_buffer.annotate(15, "deploy");
+        ring_buffer
+            .resize(2, ShrinkPolicy::MergeIntoOldest)
+            .unwrap();
+        let starts: Vec<u64> = ring_buffer.windows().map(|(ts, _)| ts).collect();
+        assert_eq!(starts, vec![40, 50]);
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 2);
+        assert!(ring_buffer.annotations().is_empty());
+        ring_buffer.resize(1, ShrinkPolicy::Drop).unwrap();
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 6);
+        ring_buffer.insert(7, 60).unwrap();
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 7);
+        assert!(ring_buffer.resize(0, ShrinkPolicy::Drop).is_err());
+    }
+    #[test]
+    fn test_estimate_quantile_between() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 10, 0, 100);
+        for (value, ts) in [(1, 0), (2, 10), (3, 20), (4, 30)] {
+            ring_buffer.insert(value, ts).unwrap();
+        }
+        assert_eq!(
+            ring_buffer.estimate_quantile_between(0.0, 10, 30).unwrap(),
+            2
+        );
+        assert_eq!(
+            ring_buffer.estimate_quantile_between(1.0, 10, 30).unwrap(),
+            3
+        );
+        // Half of the [10, 20) window's single value rounds up to one; a tenth of the
+        // [20, 30) window's rounds down to nothing
+        assert_eq!(
+            ring_buffer.estimate_quantile_between(1.0, 15, 21).unwrap(),
+            2
+        );
+        let pruned: Vec<u64> = ring_buffer
+            .windows_between(15, 21)
+            .map(|(ts, _)| ts)
+            .collect();
+        assert_eq!(pruned, vec![10, 20]);
+        assert_eq!(ring_buffer.windows_between(0, 1000).count(), 4);
+        assert_eq!(ring_buffer.windows_between(40, 1000).count(), 0);
+        assert_eq!(
+            ring_buffer.estimate_quantile_between(0.0, 35, 100).unwrap(),
+            4
+        );
+        assert!(ring_buffer.estimate_quantile_between(0.5, 40, 50).is_err());
+        assert!(ring_buffer.estimate_quantile_between(0.5, 20, 20).is_err());
+    }
+    #[test]
+    fn test_boundary_timestamps() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        ring_buffer.insert(100, 0).unwrap();
+        assert!(ring_buffer.insert(101, 0).is_err());
+        assert_eq!(ring_buffer.current_window_start(), Some(0));
+
+        // u64::MAX - 5 is a multiple of 10, and that window's end would overflow
+        let last = u64::MAX - 5;
+        ring_buffer.insert(7, last).unwrap();
+        ring_buffer.insert(8, u64::MAX).unwrap();
+        assert!(!ring_buffer.would_rotate(u64::MAX));
+        assert_eq!(ring_buffer.current_window_start(), Some(last));
+        let starts: Vec<u64> = ring_buffer.windows().map(|(ts, _)| ts).collect();
+        assert_eq!(starts, vec![last - 20, last - 10, last]);
+        assert_eq!(ring_buffer.estimate_quantile(1.0).unwrap(), 8);
+        assert_eq!(
+            ring_buffer
+                .estimate_quantile_between(0.0, last, u64::MAX)
+                .unwrap(),
+            7
+        );
+        let resampled = ring_buffer.resample(7).unwrap();
+        let total: usize = resampled.windows().iter().map(|(_, w)| w.val_count).sum();
+        assert_eq!(total, 2);
+
+        // Unit windows reach u64::MAX itself, from a buffer whose current slot is not 0
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 1, 0, 100);
+        ring_buffer.insert(1, 0).unwrap();
+        ring_buffer.insert(2, 1).unwrap();
+        ring_buffer.insert(3, u64::MAX).unwrap();
+        assert_eq!(ring_buffer.current_window_start(), Some(u64::MAX));
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 3);
+        // Only the window at u64::MAX still holds values
+        assert_eq!(ring_buffer.resample(1).unwrap().windows().len(), 1);
+
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, u64::MAX, 0, 100);
+        ring_buffer.insert(1, u64::MAX - 1).unwrap();
+        ring_buffer.insert(2, u64::MAX).unwrap();
+        assert_eq!(ring_buffer.current_window_start(), Some(u64::MAX));
+        assert!(
+            TimeBasedRingBuffer::new(0, 10, 0, 100)
+                .insert(1, 0)
+                .is_err()
+        );
+    }
+}
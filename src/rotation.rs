@@ -0,0 +1,160 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::estimator::QuantileEstimator;
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+type RotateFn = dyn FnMut(&mut Rotation<'_>) + Send;
+
+/// Work scheduled by a rotation hook, run with mutable access once the rotation is done.
+pub(crate) type FollowUp = Box<dyn FnOnce(&mut TimeBasedRingBuffer)>;
+
+/// The hook sits behind a mutex, so buffers holding an `FnMut` stay `Sync`. It is only
+/// ever locked through `&mut`, so the lock is never contended.
+pub(crate) struct RotationHook(Mutex<Box<RotateFn>>);
+
+impl fmt::Debug for RotationHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RotationHook")
+    }
+}
+
+/// What a rotation hook sees: the buffer as it was when the current window completed,
+/// before any window was recycled.
+///
+/// The view is read-only, so every query of the buffer is available from inside the
+/// hook. Changes go through [`schedule`](Self::schedule) instead.
+pub struct Rotation<'a> {
+    buffer: &'a TimeBasedRingBuffer,
+    timestamp: u64,
+    follow_ups: Vec<FollowUp>,
+}
+
+impl<'a> Rotation<'a> {
+    /// Returns the buffer, with the completed window still current.
+    pub fn buffer(&self) -> &'a TimeBasedRingBuffer {
+        self.buffer
+    }
+
+    /// Returns the window that just completed, with its start timestamp.
+    pub fn completed(&self) -> (u64, &'a QuantileEstimator) {
+        self.buffer
+            .windows()
+            .last()
+            .expect("a rotating buffer has a current window")
+    }
+
+    /// Returns the windows the rotation evicts, oldest first. When time jumps ahead by
+    /// the whole retention, this includes the completed window.
+    pub fn evicted(&self) -> impl Iterator<Item = (u64, &'a QuantileEstimator)> {
+        let capacity = self.buffer.capacity();
+        let evicted = (self.steps().min(capacity as u64)) as usize;
+        self.buffer.windows_by_age(capacity - evicted..capacity)
+    }
+
+    /// Returns the start of the window the triggering timestamp falls in.
+    pub fn next_window_start(&self) -> u64 {
+        let current = self.buffer.current_window_start().unwrap_or(0);
+        current + self.steps() * self.buffer.duration()
+    }
+
+    /// Runs `follow_up` with mutable access to the buffer once the rotation is done and
+    /// the value that triggered it is recorded. Follow-ups may insert, and any rotation
+    /// they cause runs the hook again.
+    pub fn schedule(&mut self, follow_up: impl FnOnce(&mut TimeBasedRingBuffer) + 'static) {
+        self.follow_ups.push(Box::new(follow_up));
+    }
+
+    fn steps(&self) -> u64 {
+        let current = self.buffer.current_window_start().unwrap_or(0);
+        (self.timestamp - current) / self.buffer.duration()
+    }
+}
+
+impl TimeBasedRingBuffer {
+    /// Calls `hook` every time inserting completes the current window, replacing any
+    /// previous hook. See [`Rotation`].
+    pub fn on_rotate<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut Rotation<'_>) + Send + 'static,
+    {
+        self.rotation_hook = Some(RotationHook(Mutex::new(Box::new(hook))));
+    }
+
+    /// Runs the rotation hook, if any, for a rotation up to `timestamp` and returns the
+    /// follow-ups it scheduled. The hook is taken out while it runs, so it sees the
+    /// buffer through a shared borrow and cannot be re-entered.
+    pub(crate) fn notify_rotation(&mut self, timestamp: u64) -> Vec<FollowUp> {
+        let Some(RotationHook(mut hook)) = self.rotation_hook.take() else {
+            return Vec::new();
+        };
+        let mut rotation = Rotation {
+            buffer: self,
+            timestamp,
+            follow_ups: Vec::new(),
+        };
+        (hook.get_mut().unwrap_or_else(|e| e.into_inner()))(&mut rotation);
+        let follow_ups = rotation.follow_ups;
+        self.rotation_hook = Some(RotationHook(hook));
+        follow_ups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    #[test]
+    fn test_rotation_hook() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, 10, 0, 100);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        ring_buffer.on_rotate(move |rotation: &mut Rotation<'_>| {
+            let (start, window) = rotation.completed();
+            let evicted: Vec<u64> = rotation.evicted().map(|(ts, _)| ts).collect();
+            // Queries see the state before the rotation
+            let p100 = rotation.buffer().estimate_quantile(1.0).unwrap();
+            log.lock()
+                .unwrap()
+                .push((start, window.val_count, evicted, p100));
+            let next = rotation.next_window_start();
+            rotation.schedule(move |buffer| buffer.annotate(next, "rotated"));
+        });
+        ring_buffer.insert(5, 0).unwrap();
+        ring_buffer.insert(6, 3).unwrap();
+        ring_buffer.insert(7, 10).unwrap();
+        ring_buffer.insert(8, 45).unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(0, 2, vec![], 6), (10, 1, vec![0, 10], 7)]
+        );
+        let annotated: Vec<u64> = ring_buffer
+            .annotations()
+            .iter()
+            .map(|a| a.timestamp)
+            .collect();
+        assert_eq!(annotated, vec![40]);
+        assert_eq!(ring_buffer.estimate_quantile(0.0).unwrap(), 8);
+    }
+    #[test]
+    fn test_follow_up_rotating_again() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 10, 0, 100);
+        let mut scheduled = false;
+        ring_buffer.on_rotate(move |rotation: &mut Rotation<'_>| {
+            if !scheduled {
+                scheduled = true;
+                let next = rotation.next_window_start();
+                // Rotates the buffer once more, past the triggering timestamp
+                rotation.schedule(move |buffer| buffer.insert(99, next + 10).unwrap());
+            }
+        });
+        ring_buffer.insert(1, 5).unwrap();
+        ring_buffer.insert(2, 15).unwrap();
+        let windows: Vec<(u64, u64)> = ring_buffer
+            .windows()
+            .map(|(start, w)| (start, w.max().unwrap_or(0)))
+            .collect();
+        // The triggering value stays in the window covering its timestamp
+        assert_eq!(windows, vec![(0, 1), (10, 2), (20, 99)]);
+    }
+}
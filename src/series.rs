@@ -0,0 +1,485 @@
+use std::collections::BTreeMap;
+
+use crate::annotation::{Annotation, annotations_in};
+use crate::estimator::QuantileEstimator;
+use crate::fraction::{Fraction, IntoFraction};
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// Low, middle and high percentiles of one window, as needed for shaded latency bands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Band {
+    /// Start timestamp of the window.
+    pub start: u64,
+    pub low: u64,
+    pub mid: u64,
+    pub high: u64,
+    /// Annotations attached within the window.
+    pub annotations: Vec<Annotation>,
+}
+
+/// Which side of the threshold an [`Excursion`] is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Direction {
+    /// The quantile is greater than the threshold, e.g. a latency spike.
+    Above,
+    /// The quantile is less than the threshold, e.g. a dip in payload sizes.
+    Below,
+}
+
+/// A run of consecutive windows in which a quantile stayed on one side of a threshold,
+/// such as "p99 exceeded 500 ms from 12:01 to 12:07".
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Excursion {
+    /// Start of the first window past the threshold.
+    pub start: u64,
+    /// End of the last window past the threshold.
+    pub end: u64,
+    /// Value of the quantile farthest past the threshold: the highest for
+    /// [`Above`](Direction::Above), the lowest for [`Below`](Direction::Below).
+    pub peak: u64,
+    pub direction: Direction,
+    /// True if the latest window is still past the threshold.
+    pub ongoing: bool,
+}
+
+impl Excursion {
+    /// Returns how long the quantile stayed past the threshold, `end - start`.
+    pub fn duration(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+impl TimeBasedRingBuffer {
+    /// Re-aggregates the retained windows onto windows of `step` duration.
+    /// See [`Snapshot::resample`].
+    pub fn resample(&self, step: u64) -> Result<Snapshot, &'static str> {
+        self.snapshot().resample(step)
+    }
+
+    /// Returns the `low`, `mid` and `high` percentiles of every non-empty window, oldest
+    /// first, computing all three in one pass over each window.
+    pub fn bands(
+        &self,
+        low: impl IntoFraction,
+        mid: impl IntoFraction,
+        high: impl IntoFraction,
+    ) -> Result<Vec<Band>, &'static str> {
+        let fractions = [
+            low.into_fraction()?,
+            mid.into_fraction()?,
+            high.into_fraction()?,
+        ];
+        let windows = self.windows();
+        bands(
+            windows,
+            self.duration(),
+            &self.annotations,
+            fractions.map(Fraction::get),
+        )
+    }
+
+    /// Returns the excursions of the quantile at `fraction` past `threshold` in
+    /// `direction`, oldest first. Each window's quantile is compared on its own; a window
+    /// is past the threshold if its quantile is strictly greater, or strictly less for
+    /// [`Below`](Direction::Below). An empty window has no quantile and ends an excursion
+    /// either way.
+    pub fn crossings(
+        &self,
+        fraction: impl IntoFraction,
+        threshold: u64,
+        direction: Direction,
+    ) -> Result<Vec<Excursion>, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        crossings(
+            self.windows(),
+            self.duration(),
+            fraction,
+            threshold,
+            direction,
+        )
+    }
+}
+
+impl Snapshot {
+    /// Re-aggregates the windows onto windows of `step` duration aligned to multiples of
+    /// `step`, e.g. to serve 5 second resolution from 1 second windows.
+    ///
+    /// Source windows falling entirely inside a target window are merged exactly. A source
+    /// window straddling several target windows has its counts split in proportion to the
+    /// overlap, assuming values arrived uniformly over the window; this is an approximation,
+    /// but the total count is always preserved. Target windows remember which of their
+    /// values were interpolated from coarser buckets or rounded, as their sources did.
+    ///
+    /// Only target windows overlapping a non-empty source window are built, so gaps between
+    /// sparse windows, e.g. after merging snapshots far apart in time, cost nothing.
+    pub fn resample(&self, step: u64) -> Result<Snapshot, &'static str> {
+        if step == 0 {
+            return Err("Step must be greater than zero");
+        }
+        let len = (self.end - self.start + 1) as usize;
+        // Ends are computed in u128, so a window ending past u64::MAX ends at 2^64.
+        let end_of = |start: u64, duration: u64| (start as u128 + duration as u128).min(1 << 64);
+        let mut counts: BTreeMap<u64, Vec<usize>> = BTreeMap::new();
+        // Source windows feeding each target, whose spread ranges and count error it takes on
+        let mut sources: BTreeMap<u64, Vec<&QuantileEstimator>> = BTreeMap::new();
+        for (window_start, window) in &self.windows {
+            if window.val_count == 0 {
+                continue;
+            }
+            let window_end = end_of(*window_start, self.duration);
+            let length = window_end - *window_start as u128;
+            // Target windows overlapped by this window, with the cumulative overlap so far.
+            let mut pieces = Vec::new();
+            let mut covered = 0;
+            let mut target_start = window_start - window_start % step;
+            loop {
+                let lo = target_start.max(*window_start) as u128;
+                let hi = end_of(target_start, step).min(window_end);
+                covered += hi - lo;
+                pieces.push((target_start, covered));
+                counts.entry(target_start).or_insert_with(|| vec![0; len]);
+                sources.entry(target_start).or_default().push(window);
+                match target_start.checked_add(step) {
+                    Some(next) if (next as u128) < window_end => target_start = next,
+                    _ => break,
+                }
+            }
+            for (i, &count) in window.quantiles.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                let mut assigned = 0;
+                for &(target, covered) in &pieces {
+                    let scaled = 2 * count as u128 * covered + length;
+                    let share = (scaled / (2 * length)) as usize;
+                    counts.get_mut(&target).expect("inserted above")[i] += share - assigned;
+                    assigned = share;
+                }
+            }
+        }
+        let windows = counts
+            .into_iter()
+            .map(|(start, c)| {
+                let mut window = QuantileEstimator::from_counts(self.start, self.end, c);
+                for source in &sources[&start] {
+                    window.inherit_error(source);
+                }
+                (start, window)
+            })
+            .collect();
+        Ok(Snapshot {
+            start: self.start,
+            end: self.end,
+            duration: step,
+            windows,
+            contributors: self.contributors.clone(),
+            contributor_index: self.contributor_index.clone(),
+            annotations: self.annotations.clone(),
+            audit: self.audit.clone(),
+        })
+    }
+
+    /// Returns the `low`, `mid` and `high` percentiles of every non-empty window.
+    /// See [`TimeBasedRingBuffer::bands`].
+    pub fn bands(
+        &self,
+        low: impl IntoFraction,
+        mid: impl IntoFraction,
+        high: impl IntoFraction,
+    ) -> Result<Vec<Band>, &'static str> {
+        let fractions = [
+            low.into_fraction()?,
+            mid.into_fraction()?,
+            high.into_fraction()?,
+        ];
+        let windows = self.windows.iter().map(|(ts, w)| (*ts, w));
+        bands(
+            windows,
+            self.duration,
+            &self.annotations,
+            fractions.map(Fraction::get),
+        )
+    }
+
+    /// Returns the excursions of the quantile at `fraction` past `threshold`.
+    /// See [`TimeBasedRingBuffer::crossings`].
+    pub fn crossings(
+        &self,
+        fraction: impl IntoFraction,
+        threshold: u64,
+        direction: Direction,
+    ) -> Result<Vec<Excursion>, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        let windows = self.windows.iter().map(|(ts, w)| (*ts, w));
+        crossings(windows, self.duration, fraction, threshold, direction)
+    }
+}
+
+fn crossings<'a>(
+    windows: impl Iterator<Item = (u64, &'a QuantileEstimator)>,
+    duration: u64,
+    fraction: f64,
+    threshold: u64,
+    direction: Direction,
+) -> Result<Vec<Excursion>, &'static str> {
+    let past = |value: u64| match direction {
+        Direction::Above => value > threshold,
+        Direction::Below => value < threshold,
+    };
+    let mut excursions: Vec<Excursion> = Vec::new();
+    let mut inside = false;
+    for (start, window) in windows {
+        let end = start.saturating_add(duration);
+        let value = match window.val_count {
+            0 => None,
+            _ => Some(window.estimate_quantile(fraction)?),
+        };
+        match (value.filter(|&v| past(v)), excursions.last_mut()) {
+            // Snapshots may skip windows, and a gap ends an excursion like an empty window
+            (Some(value), Some(last)) if inside && last.end == start => {
+                last.end = end;
+                last.peak = match direction {
+                    Direction::Above => last.peak.max(value),
+                    Direction::Below => last.peak.min(value),
+                };
+            }
+            (Some(value), _) => excursions.push(Excursion {
+                start,
+                end,
+                peak: value,
+                direction,
+                ongoing: false,
+            }),
+            (None, _) => {}
+        }
+        inside = value.is_some_and(past);
+    }
+    if inside && let Some(last) = excursions.last_mut() {
+        last.ongoing = true;
+    }
+    Ok(excursions)
+}
+
+fn bands<'a>(
+    windows: impl Iterator<Item = (u64, &'a QuantileEstimator)>,
+    duration: u64,
+    annotations: &[Annotation],
+    [low, mid, high]: [f64; 3],
+) -> Result<Vec<Band>, &'static str> {
+    if !(low <= mid && mid <= high) {
+        return Err("Band fractions must be ordered low <= mid <= high");
+    }
+    let mut bands = Vec::new();
+    for (start, window) in windows {
+        if window.val_count == 0 {
+            continue;
+        }
+        let q = window.estimate_quantiles(&[low, mid, high])?;
+        bands.push(Band {
+            start,
+            low: q[0],
+            mid: q[1],
+            high: q[2],
+            annotations: annotations_in(annotations, start, duration).to_vec(),
+        });
+    }
+    Ok(bands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate::WindowParts;
+    #[test]
+    fn test_resample() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(6, 1, 0, 100);
+        for ts in 0..6 {
+            ring_buffer.insert(ts * 10, ts).unwrap();
+            ring_buffer.insert(ts * 10 + 1, ts).unwrap();
+        }
+        let coarse = ring_buffer.resample(5).unwrap();
+        assert_eq!(coarse.duration(), 5);
+        let starts: Vec<u64> = coarse.windows().iter().map(|(ts, _)| *ts).collect();
+        assert_eq!(starts, vec![0, 5]);
+        assert_eq!(coarse.windows()[0].1.val_count, 10);
+        assert_eq!(coarse.windows()[1].1.val_count, 2);
+        assert_eq!(coarse.windows()[0].1.estimate_quantile(1.0).unwrap(), 41);
+
+        // Splitting 5 unit windows of 4 values into 2 unit steps keeps the total
+        let mut ring_buffer = TimeBasedRingBuffer::new(2, 5, 0, 100);
+        for v in [1, 1, 1, 1] {
+            ring_buffer.insert(v, 5).unwrap();
+        }
+        let fine = ring_buffer.resample(2).unwrap();
+        let shares: Vec<(u64, usize)> = fine
+            .windows()
+            .iter()
+            .map(|(ts, w)| (*ts, w.val_count))
+            .collect();
+        // The empty window at 0 produces no targets
+        assert_eq!(shares, vec![(4, 1), (6, 1), (8, 2)]);
+        assert_eq!(ring_buffer.resample(5).unwrap().windows().len(), 1);
+        assert!(ring_buffer.resample(0).is_err());
+
+        // Windows far apart only build the targets they overlap
+        let mut counts = vec![0; 101];
+        counts[7] = 1;
+        let window = |start| WindowParts {
+            start,
+            val_count: 1,
+            counts: counts.clone(),
+        };
+        let windows = vec![window(0), window(10 << 40)];
+        let sparse = Snapshot::from_parts(0, 100, 10, windows).unwrap();
+        let starts: Vec<u64> = sparse
+            .resample(1)
+            .unwrap()
+            .windows()
+            .iter()
+            .map(|(ts, _)| *ts)
+            .collect();
+        assert_eq!(starts.len(), 20);
+        assert_eq!((starts[9], starts[10]), (9, 10 << 40));
+    }
+    #[test]
+    fn test_bands() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 10, 0, 1000);
+        for v in 1..=100 {
+            ring_buffer.insert(v, 0).unwrap();
+        }
+        for v in 1..=100 {
+            ring_buffer.insert(v * 2, 20).unwrap();
+        }
+        let bands = ring_buffer.bands(0.05, 0.5, 0.95).unwrap();
+        assert_eq!(
+            bands,
+            vec![
+                Band {
+                    start: 0,
+                    low: 5,
+                    mid: 50,
+                    high: 95,
+                    annotations: vec![],
+                },
+                Band {
+                    start: 20,
+                    low: 10,
+                    mid: 100,
+                    high: 190,
+                    annotations: vec![],
+                },
+            ]
+        );
+        assert_eq!(
+            ring_buffer.snapshot().bands(0.05, 0.5, 0.95).unwrap(),
+            bands
+        );
+        assert!(ring_buffer.bands(0.5, 0.25, 0.75).is_err());
+        assert!(ring_buffer.bands(0.25, 0.5, 1.5).is_err());
+    }
+    #[test]
+    fn test_crossings() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(10, 60, 0, 1000);
+        // p99 per minute: 100, 600, 700, 200, (empty), 800, 900
+        let minutes = [
+            Some(100),
+            Some(600),
+            Some(700),
+            Some(200),
+            None,
+            Some(800),
+            Some(900),
+        ];
+        for (minute, p99) in minutes.into_iter().enumerate() {
+            let ts = minute as u64 * 60;
+            if let Some(p99) = p99 {
+                for i in 0..100 {
+                    ring_buffer
+                        .insert(if i < 98 { 50 } else { p99 }, ts)
+                        .unwrap();
+                }
+            }
+        }
+        let excursions = ring_buffer.crossings(0.99, 500, Direction::Above).unwrap();
+        assert_eq!(
+            excursions,
+            vec![
+                Excursion {
+                    start: 60,
+                    end: 180,
+                    peak: 700,
+                    direction: Direction::Above,
+                    ongoing: false,
+                },
+                Excursion {
+                    start: 300,
+                    end: 420,
+                    peak: 900,
+                    direction: Direction::Above,
+                    ongoing: true,
+                },
+            ]
+        );
+        assert_eq!(excursions[0].duration(), 120);
+        assert_eq!(
+            ring_buffer
+                .snapshot()
+                .crossings(0.99, 500, Direction::Above)
+                .unwrap(),
+            excursions
+        );
+        assert!(
+            ring_buffer
+                .crossings(0.5, 500, Direction::Above)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(ring_buffer.crossings(1.5, 500, Direction::Above).is_err());
+
+        // The same p99s dipping below 650: minutes 0 and 1, then minute 3 up to the gap
+        let dips = ring_buffer.crossings(0.99, 650, Direction::Below).unwrap();
+        assert_eq!(
+            dips,
+            vec![
+                Excursion {
+                    start: 0,
+                    end: 120,
+                    peak: 100,
+                    direction: Direction::Below,
+                    ongoing: false,
+                },
+                Excursion {
+                    start: 180,
+                    end: 240,
+                    peak: 200,
+                    direction: Direction::Below,
+                    ongoing: false,
+                },
+            ]
+        );
+        assert_eq!(
+            ring_buffer
+                .snapshot()
+                .crossings(0.99, 650, Direction::Below)
+                .unwrap(),
+            dips
+        );
+        // Equal to the threshold is on neither side
+        assert!(
+            ring_buffer
+                .crossings(0.99, 900, Direction::Above)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            ring_buffer
+                .crossings(0.99, 100, Direction::Below)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::estimator::QuantileEstimator;
+use crate::snapshot::Snapshot;
+
+/// The Gaussian kernel is truncated at this many bandwidths on each side.
+const KERNEL_SPAN: f64 = 3.0;
+
+/// A local maximum in the distribution.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Mode {
+    /// Value at the peak. For a flat peak, the middle of the plateau.
+    pub value: u64,
+    /// Count (or density) at the peak.
+    pub height: f64,
+    /// How far the peak rises above the higher of the two valleys separating it from
+    /// any taller peak or the edge of the range.
+    pub prominence: f64,
+}
+
+impl QuantileEstimator {
+    /// Returns up to `max_modes` local maxima of the bucket counts whose prominence is at
+    /// least `min_prominence`, most prominent first. Two well separated modes indicate a
+    /// bimodal distribution, such as cache hits versus misses.
+    pub fn modes(&self, max_modes: usize, min_prominence: f64) -> Vec<Mode> {
+        let counts: Vec<f64> = self.quantiles.iter().map(|&c| c as f64).collect();
+        find_modes(&counts, self.start, max_modes, min_prominence)
+    }
+
+    /// Returns a density estimate over the range, one entry per value, obtained by
+    /// smoothing the bucket counts with a Gaussian kernel of standard deviation
+    /// `bandwidth` (in value units). The result sums to 1, or is all zeros when no values
+    /// were added. A bandwidth of 0 returns the normalized counts unchanged.
+    pub fn smoothed_pdf(&self, bandwidth: f64) -> Vec<f64> {
+        let mut pdf = vec![0.0; self.quantiles.len()];
+        if self.val_count == 0 {
+            return pdf;
+        }
+        let total = self.val_count as f64;
+        let weights = kernel(bandwidth);
+        let reach = weights.len() - 1;
+        for (i, &count) in self.quantiles.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            // Renormalize the kernel where it is cut off by the edges of the range.
+            let lo = i.saturating_sub(reach);
+            let hi = (i + reach).min(pdf.len() - 1);
+            let mass: f64 = (lo..=hi).map(|j| weights[i.abs_diff(j)]).sum();
+            let scale = count as f64 / total / mass;
+            for (j, p) in pdf.iter_mut().enumerate().take(hi + 1).skip(lo) {
+                *p += weights[i.abs_diff(j)] * scale;
+            }
+        }
+        pdf
+    }
+
+    /// Like [`modes`](Self::modes), but on the counts smoothed with `bandwidth`, which
+    /// suppresses spurious peaks caused by sampling noise. Heights and prominences stay
+    /// in count units.
+    pub fn smoothed_modes(
+        &self,
+        bandwidth: f64,
+        max_modes: usize,
+        min_prominence: f64,
+    ) -> Vec<Mode> {
+        let total = self.val_count as f64;
+        let counts: Vec<f64> = self
+            .smoothed_pdf(bandwidth)
+            .iter()
+            .map(|p| p * total)
+            .collect();
+        find_modes(&counts, self.start, max_modes, min_prominence)
+    }
+
+    /// Returns the Shannon entropy of the distribution in bits: 0 when every value is
+    /// identical, log2(n) when values are spread evenly over n buckets.
+    pub fn entropy(&self) -> f64 {
+        let total = self.val_count as f64;
+        self.quantiles
+            .iter()
+            .filter(|&&c| c > 0)
+            .map(|&c| {
+                let p = c as f64 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    /// Returns the Gini coefficient of the counts over the occupied buckets: 0 when all
+    /// observed values are equally frequent, approaching 1 as the mass concentrates on
+    /// a few of them.
+    pub fn gini(&self) -> f64 {
+        let mut counts: Vec<usize> = self.quantiles.iter().copied().filter(|&c| c > 0).collect();
+        if counts.len() < 2 {
+            return 0.0;
+        }
+        counts.sort_unstable();
+        let n = counts.len() as f64;
+        let weighted: f64 = counts
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (i + 1) as f64 * c as f64)
+            .sum();
+        2.0 * weighted / (n * self.val_count as f64) - (n + 1.0) / n
+    }
+}
+
+impl Snapshot {
+    /// Returns the smoothed density of all windows combined.
+    /// See [`QuantileEstimator::smoothed_pdf`].
+    pub fn smoothed_pdf(&self, bandwidth: f64) -> Vec<f64> {
+        self.combined().smoothed_pdf(bandwidth)
+    }
+}
+
+/// Returns the one-sided Gaussian kernel weights for offsets `0..=reach`.
+fn kernel(bandwidth: f64) -> Vec<f64> {
+    if bandwidth <= 0.0 || !bandwidth.is_finite() {
+        return vec![1.0];
+    }
+    let reach = (bandwidth * KERNEL_SPAN).ceil() as usize;
+    (0..=reach)
+        .map(|d| (-0.5 * (d as f64 / bandwidth).powi(2)).exp())
+        .collect()
+}
+
+/// Finds the most prominent peaks of `series`, whose first element corresponds to `start`.
+pub(crate) fn find_modes(
+    series: &[f64],
+    start: u64,
+    max_modes: usize,
+    min_prominence: f64,
+) -> Vec<Mode> {
+    // Collapse plateaus so neighbouring runs always differ in height.
+    let mut runs: Vec<(f64, usize, usize)> = Vec::new();
+    for (i, &height) in series.iter().enumerate() {
+        match runs.last_mut() {
+            Some((h, _, len)) if *h == height => *len += 1,
+            _ => runs.push((height, i, 1)),
+        }
+    }
+    let heights: Vec<f64> = runs.iter().map(|r| r.0).collect();
+    let left_bases = bases(heights.iter().copied());
+    let mut right_bases = bases(heights.iter().rev().copied());
+    right_bases.reverse();
+
+    let mut modes: Vec<Mode> = Vec::new();
+    for (r, &(height, first, len)) in runs.iter().enumerate() {
+        let left_lower = r == 0 || heights[r - 1] < height;
+        let right_lower = r + 1 == runs.len() || heights[r + 1] < height;
+        if height <= 0.0 || !left_lower || !right_lower {
+            continue;
+        }
+        let prominence = height - left_bases[r].max(right_bases[r]);
+        if prominence >= min_prominence {
+            modes.push(Mode {
+                value: start + (first + (len - 1) / 2) as u64,
+                height,
+                prominence,
+            });
+        }
+    }
+    modes.sort_by(|a, b| {
+        b.prominence
+            .total_cmp(&a.prominence)
+            .then(a.value.cmp(&b.value))
+    });
+    modes.truncate(max_modes);
+    modes
+}
+
+/// For each element, the minimum between it and the nearest strictly higher element
+/// before it (or the start of the series), computed with a monotonic stack.
+fn bases(heights: impl Iterator<Item = f64>) -> Vec<f64> {
+    // Each entry holds a height and the minimum of the segment it closes.
+    let mut stack: Vec<(f64, f64)> = Vec::new();
+    let mut result = Vec::new();
+    for height in heights {
+        let mut segment_min = height;
+        while let Some(&(top, top_min)) = stack.last() {
+            if top > height {
+                break;
+            }
+            segment_min = segment_min.min(top_min);
+            stack.pop();
+        }
+        stack.push((height, segment_min));
+        result.push(segment_min);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_modes_bimodal() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        for (value, count) in [
+            (9, 3),
+            (10, 10),
+            (11, 4),
+            (50, 1),
+            (80, 2),
+            (81, 6),
+            (82, 6),
+            (83, 1),
+        ] {
+            for _ in 0..count {
+                estimator.add_value(value).unwrap();
+            }
+        }
+        let modes = estimator.modes(5, 2.0);
+        assert_eq!(modes.len(), 2);
+        assert_eq!(
+            modes[0],
+            Mode {
+                value: 10,
+                height: 10.0,
+                prominence: 10.0
+            }
+        );
+        assert_eq!(
+            modes[1],
+            Mode {
+                value: 81,
+                height: 6.0,
+                prominence: 6.0
+            }
+        );
+        assert_eq!(estimator.modes(1, 0.0).len(), 1);
+        assert_eq!(estimator.modes(5, 0.0).len(), 3);
+        assert!(QuantileEstimator::new(0, 10).modes(5, 0.0).is_empty());
+    }
+    #[test]
+    fn test_smoothed_pdf() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        assert!(estimator.smoothed_pdf(2.0).iter().all(|&p| p == 0.0));
+        for v in [0, 50, 50, 51] {
+            estimator.add_value(v).unwrap();
+        }
+        let raw = estimator.smoothed_pdf(0.0);
+        assert_eq!(raw[50], 0.5);
+        let smooth = estimator.smoothed_pdf(3.0);
+        assert!((smooth.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(smooth[50] < raw[50] && smooth[48] > 0.0);
+        assert_eq!(smooth[80], 0.0);
+        // Jittered samples form one smoothed mode instead of several raw ones
+        let mut jittered = QuantileEstimator::new(0, 100);
+        for v in [20, 22, 24, 26, 28, 70, 72, 74] {
+            jittered.add_value(v).unwrap();
+        }
+        assert_eq!(jittered.modes(10, 0.0).len(), 8);
+        let modes = jittered.smoothed_modes(3.0, 10, 0.1);
+        assert_eq!(modes.len(), 2);
+        assert_eq!(modes[0].value, 24);
+    }
+    #[test]
+    fn test_entropy_and_gini() {
+        let mut estimator = QuantileEstimator::new(0, 100);
+        assert_eq!(estimator.entropy(), 0.0);
+        assert_eq!(estimator.gini(), 0.0);
+        for v in [10, 20, 30, 40] {
+            estimator.add_value(v).unwrap();
+        }
+        assert_eq!(estimator.entropy(), 2.0);
+        assert_eq!(estimator.gini(), 0.0);
+        for _ in 0..96 {
+            estimator.add_value(30).unwrap();
+        }
+        assert!(estimator.entropy() < 0.5);
+        assert!((estimator.gini() - 0.72).abs() < 1e-9);
+    }
+    #[test]
+    fn test_prominence_uses_higher_valley() {
+        let modes = find_modes(&[1.0, 9.0, 4.0, 6.0, 2.0, 8.0, 0.0], 0, 10, 0.0);
+        let by_value = |v| modes.iter().find(|m| m.value == v).unwrap().prominence;
+        assert_eq!(by_value(1), 8.0);
+        assert_eq!(by_value(3), 2.0);
+        assert_eq!(by_value(5), 6.0);
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, TryLockError};
+
+use crate::fraction::IntoFraction;
+use crate::registry::SeriesConfig;
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+thread_local! {
+    /// Hash of the current thread's id, computed once per thread.
+    static THREAD_HASH: u64 = {
+        let mut hasher = DefaultHasher::new();
+        std::thread::current().id().hash(&mut hasher);
+        hasher.finish()
+    };
+}
+
+/// How a [`ShardedRingBuffer`] picks the shard an insert goes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShardStrategy {
+    /// Each thread always uses the shard picked by a hash of its id, so a thread never
+    /// contends with itself and a fixed pool of up to as many threads as shards rarely
+    /// contends at all. Threads can collide on a shard; check [`ShardStats`].
+    PerThread,
+    /// Each insert uses the shard of the CPU the thread is running on, so with as many
+    /// shards as cores only threads preempted mid-insert contend. Asks the OS with
+    /// `sched_getcpu` on Linux; elsewhere, or if the call fails, falls back to
+    /// [`PerThread`](Self::PerThread).
+    PerCore,
+    /// Inserts take the shards in turn, spreading load evenly whatever the threading
+    /// model, at the cost of a shared counter and of every thread touching every shard.
+    RoundRobin,
+}
+
+/// Per-shard counters, for tuning the number of shards and the strategy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ShardStats {
+    /// Values inserted into each shard.
+    pub inserts: Vec<usize>,
+    /// Inserts into each shard that had to wait for another thread.
+    pub contended: Vec<usize>,
+}
+
+impl ShardStats {
+    /// Returns the busiest shard's inserts divided by the mean: 1.0 when the load is even,
+    /// up to the number of shards when one shard takes everything. 1.0 before any insert.
+    pub fn imbalance(&self) -> f64 {
+        let total: usize = self.inserts.iter().sum();
+        let busiest = self.inserts.iter().copied().max().unwrap_or(0);
+        if total == 0 {
+            return 1.0;
+        }
+        busiest as f64 * self.inserts.len() as f64 / total as f64
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    buffer: Mutex<TimeBasedRingBuffer>,
+    inserts: AtomicUsize,
+    contended: AtomicUsize,
+}
+
+/// A ring buffer split into independently locked shards, so threads inserting at a high
+/// rate don't all wait on one mutex. Reads merge the shards.
+///
+/// Each shard keeps its own clock. A merged read keeps only the windows the shard with
+/// the latest window would still retain, so a shard that stopped receiving inserts
+/// doesn't hold old windows in the result.
+#[derive(Debug)]
+pub struct ShardedRingBuffer {
+    shards: Vec<Shard>,
+    config: SeriesConfig,
+    strategy: ShardStrategy,
+    next: AtomicUsize,
+}
+
+impl ShardedRingBuffer {
+    /// Creates `shards` shards, each a [`TimeBasedRingBuffer::new`] with the given
+    /// configuration, picked [`PerThread`](ShardStrategy::PerThread).
+    pub fn new(shards: usize, capacity: usize, duration: u64, start: u64, end: u64) -> Self {
+        let config = SeriesConfig {
+            capacity,
+            duration,
+            start,
+            end,
+        };
+        ShardedRingBuffer {
+            shards: (0..shards)
+                .map(|_| Shard {
+                    buffer: Mutex::new(config.ring_buffer()),
+                    inserts: AtomicUsize::new(0),
+                    contended: AtomicUsize::new(0),
+                })
+                .collect(),
+            config,
+            strategy: ShardStrategy::PerThread,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Picks shards with `strategy` instead.
+    pub fn with_strategy(mut self, strategy: ShardStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Returns the strategy picking shards.
+    pub fn strategy(&self) -> ShardStrategy {
+        self.strategy
+    }
+
+    /// Inserts a value with a timestamp into the shard the strategy picks.
+    pub fn insert(&self, value: u64, timestamp: u64) -> Result<(), &'static str> {
+        let shard = self.pick()?;
+        lock_for_insert(shard).insert(value, timestamp)?;
+        shard.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Inserts a value into the current window of the shard the strategy picks, or of the
+    /// latest shard if that one has no timestamp yet.
+    pub(crate) fn insert_current(&self, value: u64) -> Result<(), &'static str> {
+        let shard = self.pick()?;
+        // Released before looking at the other shards, which locks this one again
+        let own = lock(shard).current_window_start();
+        let now = own
+            .or_else(|| self.latest_window_start())
+            .ok_or("No timestamp recorded yet")?;
+        lock_for_insert(shard).insert(value, now)?;
+        shard.inserts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns a snapshot merging every shard, with the windows retained as of the latest
+    /// shard's current window.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut merged = self.config.ring_buffer().snapshot();
+        for shard in &self.shards {
+            let snapshot = lock(shard).snapshot();
+            merged
+                .merge(&snapshot)
+                .expect("shards share one configuration");
+        }
+        if let Some(&(latest, _)) = merged.windows.last() {
+            let retained =
+                self.config.capacity.saturating_sub(1) as u128 * self.config.duration as u128;
+            let oldest = (latest as u128).saturating_sub(retained) as u64;
+            merged.windows.retain(|(ts, _)| *ts >= oldest);
+        }
+        merged
+    }
+
+    /// Returns the quantile of all shards merged.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        self.snapshot().estimate_quantile(fraction)
+    }
+
+    /// Returns how many inserts each shard took and how many of those had to wait.
+    pub fn stats(&self) -> ShardStats {
+        let load = |counter: fn(&Shard) -> &AtomicUsize| {
+            self.shards
+                .iter()
+                .map(|shard| counter(shard).load(Ordering::Relaxed))
+                .collect()
+        };
+        ShardStats {
+            inserts: load(|shard| &shard.inserts),
+            contended: load(|shard| &shard.contended),
+        }
+    }
+
+    fn pick(&self) -> Result<&Shard, &'static str> {
+        if self.shards.is_empty() {
+            return Err("Shard count must be greater than zero");
+        }
+        let per_thread = || THREAD_HASH.with(|hash| *hash as usize);
+        let slot = match self.strategy {
+            ShardStrategy::PerThread => per_thread(),
+            ShardStrategy::PerCore => current_cpu().unwrap_or_else(per_thread),
+            ShardStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+        Ok(&self.shards[slot % self.shards.len()])
+    }
+
+    fn latest_window_start(&self) -> Option<u64> {
+        self.shards
+            .iter()
+            .filter_map(|shard| lock(shard).current_window_start())
+            .max()
+    }
+}
+
+/// Returns the CPU the calling thread is running on.
+#[cfg(target_os = "linux")]
+fn current_cpu() -> Option<usize> {
+    unsafe extern "C" {
+        fn sched_getcpu() -> std::ffi::c_int;
+    }
+    // SAFETY: sched_getcpu takes no arguments and only returns a number, -1 on failure
+    let cpu = unsafe { sched_getcpu() };
+    usize::try_from(cpu).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn current_cpu() -> Option<usize> {
+    None
+}
+
+fn lock(shard: &Shard) -> MutexGuard<'_, TimeBasedRingBuffer> {
+    shard.buffer.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks a shard to insert, counting the insert as contended if another thread held it.
+fn lock_for_insert(shard: &Shard) -> MutexGuard<'_, TimeBasedRingBuffer> {
+    match shard.buffer.try_lock() {
+        Ok(buffer) => buffer,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            shard.contended.fetch_add(1, Ordering::Relaxed);
+            lock(shard)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    #[test]
+    fn test_round_robin_spreads_inserts() {
+        let buffer =
+            ShardedRingBuffer::new(4, 3, 10, 0, 100).with_strategy(ShardStrategy::RoundRobin);
+        for v in 0..100 {
+            buffer.insert(v, 5).unwrap();
+        }
+        let stats = buffer.stats();
+        assert_eq!(stats.inserts, vec![25; 4]);
+        assert_eq!(stats.imbalance(), 1.0);
+        assert_eq!(buffer.estimate_quantile(0.5).unwrap(), 49);
+        assert_eq!(buffer.snapshot().combined().val_count, 100);
+        assert!(buffer.insert(101, 5).is_err());
+        assert_eq!(buffer.stats().inserts.iter().sum::<usize>(), 100);
+        assert!(
+            ShardedRingBuffer::new(0, 3, 10, 0, 100)
+                .insert(1, 0)
+                .is_err()
+        );
+    }
+    #[test]
+    fn test_per_thread_shards() {
+        let buffer = Arc::new(ShardedRingBuffer::new(4, 3, 10, 0, 100));
+        assert_eq!(buffer.strategy(), ShardStrategy::PerThread);
+        // One thread always lands on one shard
+        for v in 0..10 {
+            buffer.insert(v, 0).unwrap();
+        }
+        assert_eq!(buffer.stats().imbalance(), 4.0);
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let buffer = Arc::clone(&buffer);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        buffer.insert(t * 10 + i % 10, 0).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let stats = buffer.stats();
+        assert_eq!(stats.inserts.iter().sum::<usize>(), 410);
+        assert!(stats.inserts.iter().all(|n| n % 10 == 0));
+        assert_eq!(buffer.snapshot().combined().rank(100), 410);
+    }
+    #[test]
+    fn test_per_core_shards() {
+        let buffer = Arc::new(
+            ShardedRingBuffer::new(4, 3, 10, 0, 100).with_strategy(ShardStrategy::PerCore),
+        );
+        #[cfg(target_os = "linux")]
+        assert!(current_cpu().is_some());
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let buffer = Arc::clone(&buffer);
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        buffer.insert(t * 10 + i % 10, 0).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(buffer.stats().inserts.iter().sum::<usize>(), 400);
+        assert_eq!(buffer.snapshot().combined().rank(100), 400);
+        assert_eq!(buffer.estimate_quantile(0.0).unwrap(), 0);
+    }
+    #[test]
+    fn test_merged_read_drops_stale_shards() {
+        let buffer =
+            ShardedRingBuffer::new(2, 2, 10, 0, 100).with_strategy(ShardStrategy::RoundRobin);
+        buffer.insert(1, 0).unwrap();
+        buffer.insert(2, 100).unwrap();
+        buffer.insert(3, 105).unwrap();
+        let starts: Vec<u64> = buffer
+            .snapshot()
+            .windows()
+            .iter()
+            .map(|(ts, _)| *ts)
+            .collect();
+        assert_eq!(starts, vec![90, 100]);
+        assert_eq!(buffer.estimate_quantile(0.0).unwrap(), 2);
+    }
+}
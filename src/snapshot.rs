@@ -0,0 +1,410 @@
+use crate::annotation::Annotation;
+use crate::audit::AuditEntry;
+use crate::estimator::QuantileEstimator;
+use crate::fraction::{IntoFraction, checked};
+use crate::merge::{merge_all, select_between, select_quantile};
+use crate::provenance::{Provenance, fingerprint};
+use crate::report::bounds;
+
+/// A point-in-time copy of a ring buffer's retained windows, used for queries that
+/// should stay off the insert path.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) duration: u64,
+    pub(crate) windows: Vec<(u64, QuantileEstimator)>,
+    pub(crate) contributors: Vec<Provenance>,
+    /// Combined distribution of each contributor, parallel to `contributors`.
+    pub(crate) contributor_index: Option<Vec<QuantileEstimator>>,
+    pub(crate) annotations: Vec<Annotation>,
+    pub(crate) audit: Vec<AuditEntry>,
+}
+
+impl Snapshot {
+    /// Returns the duration of each window.
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    /// Returns the windows, oldest first, paired with their start timestamps.
+    pub fn windows(&self) -> &[(u64, QuantileEstimator)] {
+        &self.windows
+    }
+
+    /// Returns the quantile over the time range `[from, to)`.
+    /// See [`TimeBasedRingBuffer::estimate_quantile_between`](crate::TimeBasedRingBuffer::estimate_quantile_between).
+    pub fn estimate_quantile_between(
+        &self,
+        fraction: impl IntoFraction,
+        from: u64,
+        to: u64,
+    ) -> Result<u64, &'static str> {
+        let fraction = fraction.into_fraction()?.get();
+        if from >= to {
+            return Err("Time range is empty");
+        }
+        // Windows are sorted by start, so the overlapping ones form a contiguous slice.
+        let first = self
+            .windows
+            .partition_point(|(ts, _)| ts.saturating_add(self.duration) <= from);
+        let last = self.windows.partition_point(|(ts, _)| *ts < to);
+        let windows = self.windows[first..last.max(first)].iter();
+        select_between(
+            windows.map(|(ts, w)| (*ts, w)),
+            self.duration,
+            fraction,
+            from,
+            to,
+        )
+    }
+
+    /// Renders an aligned text table with one row per fraction, giving the percentile,
+    /// its estimated value, the number of values at or below it, and how far the true
+    /// value can be from the estimate, as for the bounds of [`report`](Self::report).
+    /// The bound is zero unless values were interpolated inside coarser buckets or counts
+    /// were rounded by [`quantized`](Self::quantized).
+    pub fn table(&self, fractions: &[impl IntoFraction]) -> Result<String, &'static str> {
+        let fractions = checked(fractions)?;
+        let combined = self.combined();
+        let values = combined.estimate_quantiles(&fractions)?;
+        let mut rows = vec![[
+            "percentile".to_string(),
+            "value".to_string(),
+            "cumulative".to_string(),
+            "bound".to_string(),
+        ]];
+        for (&fraction, &value) in fractions.iter().zip(&values) {
+            let (lower, upper) = bounds(&[&combined], fraction, value)?;
+            rows.push([
+                percentile_label(fraction),
+                value.to_string(),
+                combined.rank(value).to_string(),
+                format!("±{}", (value - lower).max(upper - value)),
+            ]);
+        }
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut table = String::new();
+        for row in &rows {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:>width$}"))
+                .collect();
+            table.push_str(&cells.join("  "));
+            table.push('\n');
+        }
+        Ok(table)
+    }
+
+    /// Returns a fingerprint of the range and window duration. Only snapshots with equal
+    /// fingerprints can be merged.
+    pub fn config_fingerprint(&self) -> u64 {
+        fingerprint(&[self.start, self.end, self.duration])
+    }
+
+    /// Attaches the provenance of the process that recorded this snapshot, replacing any
+    /// previous contributors. The provenance's config fingerprint is set from this snapshot.
+    pub fn with_provenance(mut self, mut provenance: Provenance) -> Self {
+        provenance.config_fingerprint = self.config_fingerprint();
+        self.contributors = vec![provenance];
+        self
+    }
+
+    /// Keeps each contributor's combined distribution through merges, so tail mass can be
+    /// attributed to source hosts with [`top_contributors_above`](Self::top_contributors_above).
+    ///
+    /// Call this on single-contributor snapshots before merging them. A merge with a
+    /// snapshot that has several contributors but no index of its own drops the index,
+    /// since their individual shares can no longer be told apart.
+    pub fn with_contributor_index(mut self) -> Self {
+        if self.contributor_index.is_none() && self.contributors.len() == 1 {
+            self.contributor_index = Some(vec![self.combined()]);
+        }
+        self
+    }
+
+    /// Returns the contributors with values above `value_threshold`, with the number of
+    /// such values each contributed, largest first.
+    pub fn top_contributors_above(
+        &self,
+        value_threshold: u64,
+    ) -> Result<Vec<(&Provenance, usize)>, &'static str> {
+        let index = self
+            .contributor_index
+            .as_ref()
+            .ok_or("Snapshot has no contributor index")?;
+        let mut above: Vec<(&Provenance, usize)> = self
+            .contributors
+            .iter()
+            .zip(index)
+            .map(|(p, e)| (p, e.val_count - e.rank(value_threshold)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        above.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        Ok(above)
+    }
+
+    /// Returns the provenance of every snapshot merged into this one.
+    pub fn contributors(&self) -> &[Provenance] {
+        &self.contributors
+    }
+
+    /// Merges `other` into this snapshot, summing windows with the same start timestamp
+    /// and appending its contributors. Both must share the same range and duration.
+    pub fn merge(&mut self, other: &Snapshot) -> Result<(), &'static str> {
+        self.merge_windows(other)?;
+        if let Some(index) = &mut self.contributor_index {
+            match &other.contributor_index {
+                Some(theirs) => index.extend(theirs.iter().cloned()),
+                None if other.contributors.len() == 1 => index.push(other.combined()),
+                None => self.contributor_index = None,
+            }
+        }
+        self.contributors.extend(other.contributors.iter().cloned());
+        self.annotations.extend(other.annotations.iter().cloned());
+        self.annotations.sort_by_key(|a| a.timestamp);
+        self.audit.extend(other.audit.iter().cloned());
+        self.audit.sort_by_key(|e| e.timestamp);
+        Ok(())
+    }
+
+    /// Sums the windows of `other` into this snapshot's, leaving the contributors,
+    /// annotations and audit log alone.
+    pub(crate) fn merge_windows(&mut self, other: &Snapshot) -> Result<(), &'static str> {
+        if self.config_fingerprint() != other.config_fingerprint() {
+            return Err("Snapshot configurations do not match");
+        }
+        let mut windows = Vec::with_capacity(self.windows.len().max(other.windows.len()));
+        let mut mine = std::mem::take(&mut self.windows).into_iter().peekable();
+        let mut theirs = other.windows.iter().peekable();
+        loop {
+            match (mine.peek(), theirs.peek()) {
+                (Some((a, _)), Some((b, _))) if a == b => {
+                    let (ts, mut window) = mine.next().unwrap();
+                    window.merge(&theirs.next().unwrap().1)?;
+                    windows.push((ts, window));
+                }
+                (Some((a, _)), Some((b, _))) if b < a => {
+                    windows.push(theirs.next().unwrap().clone());
+                }
+                (Some(_), _) => windows.push(mine.next().unwrap()),
+                (None, Some(_)) => windows.push(theirs.next().unwrap().clone()),
+                (None, None) => break,
+            }
+        }
+        self.windows = windows;
+        Ok(())
+    }
+
+    /// Like [`merge`](Self::merge), but first snaps every window start of both snapshots
+    /// to the nearest multiple of the duration, for producers whose window boundaries
+    /// drift with clock skew. Windows snapped onto the same start are summed.
+    ///
+    /// Fails without changing `self` if a start is more than `tolerance` away from the
+    /// grid, or if `tolerance` is half the duration or more, where snapping would be
+    /// ambiguous.
+    pub fn merge_with_tolerance(
+        &mut self,
+        other: &Snapshot,
+        tolerance: u64,
+    ) -> Result<(), &'static str> {
+        if self.config_fingerprint() != other.config_fingerprint() {
+            return Err("Snapshot configurations do not match");
+        }
+        if tolerance.saturating_mul(2) >= self.duration {
+            return Err("Tolerance must be less than half the window duration");
+        }
+        let mine = self.aligned(tolerance)?;
+        let theirs = other.aligned(tolerance)?;
+        self.windows = mine;
+        self.merge(&Snapshot {
+            windows: theirs,
+            ..other.clone()
+        })
+    }
+
+    /// Returns the windows with their starts snapped onto the duration grid.
+    fn aligned(&self, tolerance: u64) -> Result<Vec<(u64, QuantileEstimator)>, &'static str> {
+        let mut windows: Vec<(u64, QuantileEstimator)> = Vec::with_capacity(self.windows.len());
+        for (start, window) in &self.windows {
+            let below = start - start % self.duration;
+            // The next grid point doesn't exist past u64::MAX, so a start just below it
+            // only snaps down
+            let above = below.checked_add(self.duration);
+            let snapped = if start - below <= tolerance {
+                below
+            } else if let Some(above) = above.filter(|above| above - start <= tolerance) {
+                above
+            } else {
+                return Err("Window start is outside the alignment tolerance");
+            };
+            match windows.last_mut() {
+                Some((last, merged)) if *last == snapped => merged.merge(window)?,
+                _ => windows.push((snapped, window.clone())),
+            }
+        }
+        Ok(windows)
+    }
+
+    /// Returns all windows merged into a single estimator.
+    pub fn combined(&self) -> QuantileEstimator {
+        merge_all(self.windows.iter().map(|(_, w)| w))
+            .unwrap_or_else(|_| QuantileEstimator::new(self.start, self.end))
+    }
+
+    /// Returns the quantile of all windows combined.
+    pub fn estimate_quantile(&self, fraction: impl IntoFraction) -> Result<u64, &'static str> {
+        select_quantile(self.windows.iter().map(|(_, w)| w), fraction)
+    }
+}
+
+/// Formats a fraction as a percentile label, e.g. 0.999 as "p99.9".
+pub(crate) fn percentile_label(fraction: f64) -> String {
+    let percent = format!("{:.4}", fraction * 100.0);
+    let percent = percent.trim_end_matches('0').trim_end_matches('.');
+    format!("p{percent}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ring_buffer::TimeBasedRingBuffer;
+
+    use super::*;
+    #[test]
+    fn test_merge_with_tolerance() {
+        let mut host = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        host.insert(1, 0).unwrap();
+        host.insert(2, 10).unwrap();
+        let mut merged = host.snapshot();
+        let mut skewed = host.snapshot();
+        skewed.windows[0].0 = 1;
+        skewed.windows[1].0 = 19;
+        assert!(merged.clone().merge_with_tolerance(&skewed, 5).is_err());
+        merged.merge_with_tolerance(&skewed, 1).unwrap();
+        let starts: Vec<u64> = merged.windows().iter().map(|(ts, _)| *ts).collect();
+        assert_eq!(starts, vec![0, 10, 20]);
+        assert_eq!(merged.windows()[0].1.val_count, 2);
+        assert_eq!(merged.windows()[2].1.val_count, 1);
+        skewed.windows[0].0 = 3;
+        let before = merged.windows().len();
+        assert!(merged.merge_with_tolerance(&skewed, 2).is_err());
+        assert_eq!(merged.windows().len(), before);
+
+        // Near u64::MAX the grid point above would overflow
+        let mut last = TimeBasedRingBuffer::new(3, 10, 0, 100).snapshot();
+        let mut skewed = host.snapshot();
+        skewed.windows.truncate(1);
+        skewed.windows[0].0 = u64::MAX - 1;
+        assert!(last.clone().merge_with_tolerance(&skewed, 3).is_err());
+        last.merge_with_tolerance(&skewed, 4).unwrap();
+        assert_eq!(last.windows()[0].0, u64::MAX - 5);
+    }
+    #[test]
+    fn test_merge_with_provenance() {
+        let mut host_a = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        let mut host_b = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        host_a.insert(1, 0).unwrap();
+        host_a.insert(2, 10).unwrap();
+        host_b.insert(3, 10).unwrap();
+        host_b.insert(90, 20).unwrap();
+        let mut provenance = Provenance::current(0);
+        provenance.hostname = "a".to_string();
+        let mut merged = host_a.snapshot().with_provenance(provenance);
+        let mut provenance = Provenance::current(5);
+        provenance.hostname = "b".to_string();
+        let other = host_b.snapshot().with_provenance(provenance);
+        merged.merge(&other).unwrap();
+
+        let starts: Vec<u64> = merged.windows().iter().map(|(ts, _)| *ts).collect();
+        assert_eq!(starts, vec![0, 10, 20]);
+        assert_eq!(merged.windows()[1].1.val_count, 2);
+        assert_eq!(merged.estimate_quantile(1.0).unwrap(), 90);
+        let hosts: Vec<&str> = merged
+            .contributors()
+            .iter()
+            .map(|p| p.hostname.as_str())
+            .collect();
+        assert_eq!(hosts, vec!["a", "b"]);
+        assert_eq!(
+            merged.contributors()[0].config_fingerprint,
+            merged.config_fingerprint()
+        );
+        assert_eq!(
+            merged.contributors()[0].crate_version,
+            env!("CARGO_PKG_VERSION")
+        );
+
+        assert_eq!(merged.estimate_quantile_between(1.0, 0, 20).unwrap(), 3);
+        assert_eq!(merged.estimate_quantile_between(0.0, 20, 30).unwrap(), 90);
+        assert!(merged.estimate_quantile_between(0.0, 30, 40).is_err());
+        let incompatible = TimeBasedRingBuffer::new(3, 20, 0, 100).snapshot();
+        assert!(merged.merge(&incompatible).is_err());
+    }
+    #[test]
+    fn test_table() {
+        let mut buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+        for v in 1..=1000 {
+            buffer.insert(v, 0).unwrap();
+        }
+        let table = buffer.snapshot().table(&[0.5, 0.99, 0.999]).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], "percentile  value  cumulative  bound");
+        assert_eq!(lines[1], "       p50    500         500     ±0");
+        assert_eq!(lines[3], "     p99.9    999         999     ±0");
+        assert_eq!(lines.len(), 4);
+        assert!(buffer.snapshot().table(&[2.0]).is_err());
+
+        // Prometheus buckets le="10", le="50" and le="100"
+        let mut buckets = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+        buckets
+            .insert_buckets(&[10, 50, 100], &[10, 80, 10], 0)
+            .unwrap();
+        let table = buckets.snapshot().table(&[0.5, 0.99]).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        // The true median is anywhere in the le="50" bucket, 11..=50
+        assert_eq!(lines[1], "       p50     30          50    ±20");
+        assert_eq!(lines[2], "       p99     95          99    ±44");
+        assert_eq!(percentile_label(0.0), "p0");
+    }
+    #[test]
+    fn test_top_contributors_above() {
+        let snapshot_of = |host: &str, values: &[u64]| {
+            let mut buffer = TimeBasedRingBuffer::new(3, 10, 0, 1000);
+            for &v in values {
+                buffer.insert(v, 0).unwrap();
+            }
+            let mut provenance = Provenance::current(0);
+            provenance.hostname = host.to_string();
+            buffer
+                .snapshot()
+                .with_provenance(provenance)
+                .with_contributor_index()
+        };
+        let mut merged = snapshot_of("a", &[10, 20, 950]);
+        assert!(merged.top_contributors_above(0).is_ok());
+        merged
+            .merge(&snapshot_of("b", &[10, 900, 990, 999]))
+            .unwrap();
+        merged.merge(&snapshot_of("c", &[10, 11])).unwrap();
+        let top: Vec<(&str, usize)> = merged
+            .top_contributors_above(800)
+            .unwrap()
+            .into_iter()
+            .map(|(p, count)| (p.hostname.as_str(), count))
+            .collect();
+        assert_eq!(top, vec![("b", 3), ("a", 1)]);
+
+        let mut unindexed = snapshot_of("d", &[1]);
+        unindexed.contributor_index = None;
+        let mut pair = unindexed.clone();
+        pair.merge(&unindexed).unwrap();
+        merged.merge(&pair).unwrap();
+        assert!(merged.top_contributors_above(800).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use crate::fraction::IntoFraction;
+use crate::ring_buffer::TimeBasedRingBuffer;
+
+/// Tracks the durations of each stage of a request (e.g. parse, backend call, render) in
+/// parallel ring buffers that share one clock, so stages can be compared over the same
+/// time ranges.
+#[derive(Debug)]
+pub struct StagedTracker {
+    stages: Vec<(String, TimeBasedRingBuffer)>,
+}
+
+/// How a stage's quantile changed between two consecutive periods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StageGrowth {
+    pub stage: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// One stage of a [`StagedTracker`], from [`StagedTracker::stage_recorder`].
+#[derive(Debug)]
+pub struct StageRecorder<'a> {
+    pub(crate) buffer: &'a mut TimeBasedRingBuffer,
+}
+
+impl StageGrowth {
+    /// Returns `after - before`, negative when the stage got faster.
+    pub fn delta(&self) -> i128 {
+        self.after as i128 - self.before as i128
+    }
+}
+
+impl StagedTracker {
+    /// Creates a tracker with one ring buffer per stage, all sharing the same
+    /// configuration. See [`TimeBasedRingBuffer::new`].
+    pub fn new(stages: &[&str], capacity: usize, duration: u64, start: u64, end: u64) -> Self {
+        StagedTracker {
+            stages: stages
+                .iter()
+                .map(|&name| {
+                    let buffer = TimeBasedRingBuffer::new(capacity, duration, start, end);
+                    (name.to_string(), buffer)
+                })
+                .collect(),
+        }
+    }
+
+    /// Records one request's stage durations, given in the order the stages were declared.
+    pub fn record(&mut self, timestamp: u64, durations: &[u64]) -> Result<(), &'static str> {
+        if durations.len() != self.stages.len() {
+            return Err("Expected one duration per stage");
+        }
+        for ((_, buffer), &duration) in self.stages.iter_mut().zip(durations) {
+            buffer.insert(duration, timestamp)?;
+        }
+        Ok(())
+    }
+
+    /// Records a single stage's duration.
+    pub fn record_stage(
+        &mut self,
+        stage: &str,
+        duration: u64,
+        timestamp: u64,
+    ) -> Result<(), &'static str> {
+        self.stage_mut(stage)?.insert(duration, timestamp)
+    }
+
+    /// Returns a handle recording single durations of `stage`, for passing to code that
+    /// takes a [`Record`](crate::Record). Fails if the stage wasn't declared.
+    pub fn stage_recorder(&mut self, stage: &str) -> Result<StageRecorder<'_>, &'static str> {
+        Ok(StageRecorder {
+            buffer: self.stage_mut(stage)?,
+        })
+    }
+
+    /// Returns the ring buffer of a stage.
+    pub fn stage(&self, stage: &str) -> Option<&TimeBasedRingBuffer> {
+        self.stages
+            .iter()
+            .find(|(name, _)| name == stage)
+            .map(|(_, b)| b)
+    }
+
+    /// Compares each stage's quantile over `[now - lookback, now)` with the preceding period
+    /// of the same length, and returns the stages ordered by how much they grew, largest
+    /// growth first. Stages without data in both periods are left out.
+    pub fn stage_growth(
+        &self,
+        fraction: impl IntoFraction,
+        now: u64,
+        lookback: u64,
+    ) -> Vec<StageGrowth> {
+        let recent_start = now.saturating_sub(lookback);
+        let earlier_start = recent_start.saturating_sub(lookback);
+        let mut growth: Vec<StageGrowth> = self
+            .stages
+            .iter()
+            .filter_map(|(name, buffer)| {
+                let before = buffer
+                    .estimate_quantile_between(fraction, earlier_start, recent_start)
+                    .ok()?;
+                let after = buffer
+                    .estimate_quantile_between(fraction, recent_start, now)
+                    .ok()?;
+                Some(StageGrowth {
+                    stage: name.clone(),
+                    before,
+                    after,
+                })
+            })
+            .collect();
+        growth.sort_by_key(|g| std::cmp::Reverse(g.delta()));
+        growth
+    }
+
+    fn stage_mut(&mut self, stage: &str) -> Result<&mut TimeBasedRingBuffer, &'static str> {
+        self.stages
+            .iter_mut()
+            .find(|(name, _)| name == stage)
+            .map(|(_, b)| b)
+            .ok_or("Unknown stage")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_stage_growth() {
+        let mut tracker = StagedTracker::new(&["parse", "backend", "render"], 10, 60, 0, 10_000);
+        for ts in 0..300 {
+            tracker.record(ts, &[5, 100, 20]).unwrap();
+        }
+        for ts in 300..600 {
+            tracker.record(ts, &[6, 900, 20]).unwrap();
+        }
+        let growth = tracker.stage_growth(0.99, 600, 300);
+        let stages: Vec<&str> = growth.iter().map(|g| g.stage.as_str()).collect();
+        assert_eq!(stages, vec!["backend", "parse", "render"]);
+        assert_eq!(growth[0].delta(), 800);
+        assert!(tracker.record(600, &[1, 2]).is_err());
+        tracker.record_stage("render", 50, 600).unwrap();
+        assert_eq!(
+            tracker
+                .stage("render")
+                .unwrap()
+                .estimate_quantile(1.0)
+                .unwrap(),
+            50
+        );
+        assert!(tracker.record_stage("unknown", 1, 600).is_err());
+    }
+}
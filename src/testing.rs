@@ -0,0 +1,191 @@
+//! Tolerant comparisons of quantile sketches, for tests that shouldn't break when an
+//! approximate backend shifts an estimate by a bucket.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::estimator::QuantileEstimator;
+use crate::fraction::{IntoFraction, checked};
+use crate::ring_buffer::TimeBasedRingBuffer;
+use crate::snapshot::Snapshot;
+
+/// A recorder whose combined distribution can be compared, implemented by
+/// [`QuantileEstimator`], [`TimeBasedRingBuffer`] and [`Snapshot`].
+///
+/// The trait is sealed so methods can be added without breaking downstream crates.
+pub trait Sketch: sealed::Sealed {
+    /// Returns the distribution of every value the sketch holds.
+    fn distribution(&self) -> Cow<'_, QuantileEstimator>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::estimator::QuantileEstimator {}
+    impl Sealed for crate::ring_buffer::TimeBasedRingBuffer {}
+    impl Sealed for crate::snapshot::Snapshot {}
+}
+
+impl Sketch for QuantileEstimator {
+    fn distribution(&self) -> Cow<'_, QuantileEstimator> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl Sketch for TimeBasedRingBuffer {
+    fn distribution(&self) -> Cow<'_, QuantileEstimator> {
+        Cow::Owned(self.snapshot().combined())
+    }
+}
+
+impl Sketch for Snapshot {
+    fn distribution(&self) -> Cow<'_, QuantileEstimator> {
+        Cow::Owned(self.combined())
+    }
+}
+
+/// Why two sketches were not close.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Mismatch {
+    /// One sketch holds values and the other doesn't.
+    Empty,
+    /// The quantile at `fraction` of one sketch, `value`, ranks between `lower` and
+    /// `upper` in the other, farther than the tolerance from `fraction`.
+    Rank {
+        fraction: f64,
+        value: u64,
+        lower: f64,
+        upper: f64,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Empty => write!(f, "only one of the sketches holds values"),
+            Mismatch::Rank {
+                fraction,
+                value,
+                lower,
+                upper,
+            } => write!(
+                f,
+                "quantile {fraction} of one sketch is {value}, which ranks between \
+                 {lower:.6} and {upper:.6} in the other"
+            ),
+        }
+    }
+}
+
+/// Compares two sketches by rank rather than by value: they are close if, for every
+/// checked fraction, each sketch's quantile ranks within `epsilon_rank` of that fraction
+/// in the other sketch.
+///
+/// Rank tolerance holds up where value tolerance doesn't, e.g. across the gap of a
+/// bimodal distribution, where a tiny rank error moves the value a long way.
+#[derive(Debug, Clone)]
+pub struct SketchComparator {
+    epsilon_rank: f64,
+    fractions: Vec<f64>,
+}
+
+impl SketchComparator {
+    /// Creates a comparator checking every percentile from p0 to p100, plus p99.9 and
+    /// p99.99, within `epsilon_rank` (e.g. `0.01` for one percentile).
+    pub fn new(epsilon_rank: f64) -> Self {
+        let mut fractions: Vec<f64> = (0..=100).map(|p| p as f64 / 100.0).collect();
+        fractions.extend([0.999, 0.9999]);
+        SketchComparator {
+            epsilon_rank,
+            fractions,
+        }
+    }
+
+    /// Checks only `fractions` instead of the default percentiles.
+    pub fn fractions(mut self, fractions: &[impl IntoFraction]) -> Result<Self, &'static str> {
+        self.fractions = checked(fractions)?;
+        Ok(self)
+    }
+
+    /// Returns the first mismatch between `a` and `b`, if any.
+    pub fn compare(&self, a: &impl Sketch, b: &impl Sketch) -> Result<(), Mismatch> {
+        let (a, b) = (a.distribution(), b.distribution());
+        match (a.val_count, b.val_count) {
+            (0, 0) => return Ok(()),
+            (0, _) | (_, 0) => return Err(Mismatch::Empty),
+            _ => {}
+        }
+        self.check(&a, &b)?;
+        self.check(&b, &a)
+    }
+
+    /// Checks that the quantiles of `from` rank close to their fraction in `to`.
+    fn check(&self, from: &QuantileEstimator, to: &QuantileEstimator) -> Result<(), Mismatch> {
+        let values = from
+            .estimate_quantiles(&self.fractions)
+            .expect("fractions are checked and the sketch is not empty");
+        let total = to.val_count as f64;
+        for (&fraction, &value) in self.fractions.iter().zip(&values) {
+            let below = value.checked_sub(1).map_or(0, |v| to.rank(v));
+            let lower = below as f64 / total;
+            let upper = to.rank(value) as f64 / total;
+            if fraction < lower - self.epsilon_rank || fraction > upper + self.epsilon_rank {
+                return Err(Mismatch::Rank {
+                    fraction,
+                    value,
+                    lower,
+                    upper,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Asserts that two sketches agree within a rank tolerance:
+/// `assert_quantiles_close!(a, b, 0.01)`. See [`SketchComparator`].
+#[macro_export]
+macro_rules! assert_quantiles_close {
+    ($a:expr, $b:expr, $epsilon_rank:expr $(,)?) => {
+        if let Err(mismatch) =
+            $crate::testing::SketchComparator::new($epsilon_rank).compare(&$a, &$b)
+        {
+            panic!("assertion `quantiles close` failed: {mismatch}");
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_rank_tolerance() {
+        let mut a = QuantileEstimator::new(0, 1000);
+        let mut b = QuantileEstimator::new(0, 1000);
+        for v in 0..100 {
+            a.add_value(v).unwrap();
+            b.add_value(v + 1).unwrap();
+        }
+        // Shifted by one value, i.e. one percent of the ranks
+        assert_quantiles_close!(a, b, 0.011);
+        let comparator = SketchComparator::new(0.001);
+        assert!(matches!(
+            comparator.compare(&a, &b),
+            Err(Mismatch::Rank { .. })
+        ));
+        // Values far apart in a gap are still close by rank
+        let mut gap = QuantileEstimator::new(0, 1000);
+        for v in 0..100 {
+            gap.add_value(if v < 50 { v } else { v + 800 }).unwrap();
+        }
+        let mut shifted = gap.clone();
+        shifted.add_value(949).unwrap();
+        assert_quantiles_close!(gap, shifted, 0.02);
+
+        let empty = QuantileEstimator::new(0, 1000);
+        assert_eq!(comparator.compare(&a, &empty), Err(Mismatch::Empty));
+        assert!(comparator.compare(&empty, &empty.clone()).is_ok());
+        assert!(SketchComparator::new(0.0).fractions(&[1.5]).is_err());
+    }
+}
@@ -0,0 +1,462 @@
+use std::fmt;
+
+use crate::estimator::{QuantileEstimator, RANK_BLOCK};
+use crate::snapshot::Snapshot;
+
+/// Raw contents of one window, as stored outside the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowParts {
+    pub start: u64,
+    /// Total number of values recorded in the window.
+    pub val_count: usize,
+    /// One count per value of the range.
+    pub counts: Vec<usize>,
+}
+
+/// An inconsistency found while validating a snapshot or a delta received from another
+/// process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationIssue {
+    /// The window duration is zero.
+    ZeroDuration,
+    /// The range end is below its start.
+    InvalidRange { start: u64, end: u64 },
+    /// The range has more values than a window can hold one count for.
+    RangeTooWide { start: u64, end: u64 },
+    /// A window's bucket counts don't cover the range.
+    BucketCountMismatch {
+        window_start: u64,
+        expected: usize,
+        actual: usize,
+    },
+    /// A window's bucket counts don't add up to its recorded value count.
+    CountMismatch {
+        window_start: u64,
+        recorded: usize,
+        actual: usize,
+    },
+    /// A window's bucket counts add up to more than `usize::MAX`.
+    CountOverflow { window_start: u64 },
+    /// A window's per-block totals don't match its bucket counts.
+    BlockCountMismatch { window_start: u64 },
+    /// A window doesn't start on a multiple of the duration.
+    MisalignedWindow { window_start: u64 },
+    /// Windows are not in strictly increasing start order.
+    UnorderedWindow { window_start: u64 },
+    /// The contributor index doesn't have one entry per contributor.
+    ContributorIndexMismatch { contributors: usize, indexed: usize },
+    /// A delta's checksum doesn't match its contents, e.g. after corruption in transit.
+    ChecksumMismatch { expected: u64, actual: u64 },
+    /// A delta window's changes are not in strictly increasing bucket order.
+    UnorderedChange { window_start: u64 },
+    /// A delta change refers to a bucket outside the range.
+    ChangeOutOfRange { window_start: u64, index: usize },
+    /// The snapshot a delta is applied to isn't the one it was computed against.
+    BaseMismatch { expected: u64, actual: u64 },
+    /// Applying a delta didn't produce the snapshot it was computed from.
+    TargetMismatch { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationIssue::ZeroDuration => write!(f, "window duration is zero"),
+            ValidationIssue::InvalidRange { start, end } => {
+                write!(f, "range end {end} is below its start {start}")
+            }
+            ValidationIssue::RangeTooWide { start, end } => {
+                write!(
+                    f,
+                    "range {start}..={end} is too wide for one count per value"
+                )
+            }
+            ValidationIssue::BucketCountMismatch {
+                window_start,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "window {window_start} has {actual} bucket counts, expected {expected}"
+            ),
+            ValidationIssue::CountMismatch {
+                window_start,
+                recorded,
+                actual,
+            } => write!(
+                f,
+                "window {window_start} records {recorded} values but its buckets hold {actual}"
+            ),
+            ValidationIssue::CountOverflow { window_start } => {
+                write!(f, "window {window_start} counts overflow")
+            }
+            ValidationIssue::BlockCountMismatch { window_start } => {
+                write!(
+                    f,
+                    "window {window_start} block totals don't match its buckets"
+                )
+            }
+            ValidationIssue::MisalignedWindow { window_start } => {
+                write!(f, "window {window_start} is not aligned to the duration")
+            }
+            ValidationIssue::UnorderedWindow { window_start } => {
+                write!(f, "window {window_start} is out of start order")
+            }
+            ValidationIssue::ContributorIndexMismatch {
+                contributors,
+                indexed,
+            } => write!(
+                f,
+                "contributor index has {indexed} entries for {contributors} contributors"
+            ),
+            ValidationIssue::ChecksumMismatch { expected, actual } => {
+                write!(f, "delta checksum {actual:#x} does not match {expected:#x}")
+            }
+            ValidationIssue::UnorderedChange { window_start } => {
+                write!(
+                    f,
+                    "delta window {window_start} changes are out of bucket order"
+                )
+            }
+            ValidationIssue::ChangeOutOfRange {
+                window_start,
+                index,
+            } => write!(
+                f,
+                "delta window {window_start} changes bucket {index}, outside the range"
+            ),
+            ValidationIssue::BaseMismatch { .. } => {
+                write!(f, "delta base does not match, resync with a full snapshot")
+            }
+            ValidationIssue::TargetMismatch { .. } => {
+                write!(
+                    f,
+                    "delta target does not match, resync with a full snapshot"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationIssue {}
+
+/// Every issue found while validating a snapshot. Empty when the snapshot is consistent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns true if no issues were found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            return write!(f, "snapshot is consistent");
+        }
+        write!(f, "{} validation issue(s):", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, " {issue};")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+impl Snapshot {
+    /// Rebuilds a snapshot from stored parts, for example after loading it from disk,
+    /// rejecting it with a report of every inconsistency instead of accepting corrupt state.
+    pub fn from_parts(
+        start: u64,
+        end: u64,
+        duration: u64,
+        windows: Vec<WindowParts>,
+    ) -> Result<Snapshot, ValidationReport> {
+        let mut report = ValidationReport::default();
+        let Some(len) = check_config(&mut report, start, end, duration) else {
+            return Err(report);
+        };
+        let mut previous = None;
+        for window in &windows {
+            check_window_start(&mut report, window.start, duration, &mut previous);
+            if window.counts.len() != len {
+                report.issues.push(ValidationIssue::BucketCountMismatch {
+                    window_start: window.start,
+                    expected: len,
+                    actual: window.counts.len(),
+                });
+                continue;
+            }
+            let Some(actual) = checked_total(&window.counts) else {
+                report.issues.push(ValidationIssue::CountOverflow {
+                    window_start: window.start,
+                });
+                continue;
+            };
+            if actual != window.val_count {
+                report.issues.push(ValidationIssue::CountMismatch {
+                    window_start: window.start,
+                    recorded: window.val_count,
+                    actual,
+                });
+            }
+        }
+        if !report.is_valid() {
+            return Err(report);
+        }
+        let windows = windows
+            .into_iter()
+            .map(|w| {
+                (
+                    w.start,
+                    QuantileEstimator::from_counts(start, end, w.counts),
+                )
+            })
+            .collect();
+        Ok(Snapshot {
+            start,
+            end,
+            duration,
+            windows,
+            contributors: Vec::new(),
+            contributor_index: None,
+            annotations: Vec::new(),
+            audit: Vec::new(),
+        })
+    }
+
+    /// Returns the raw contents of every window, the inverse of [`from_parts`](Self::from_parts).
+    pub fn to_parts(&self) -> Vec<WindowParts> {
+        self.windows
+            .iter()
+            .map(|(start, w)| WindowParts {
+                start: *start,
+                val_count: w.val_count,
+                counts: w.quantiles.clone(),
+            })
+            .collect()
+    }
+
+    /// Checks the snapshot's internal consistency.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let Some(len) = check_config(&mut report, self.start, self.end, self.duration) else {
+            return report;
+        };
+        let mut previous = None;
+        for (window_start, window) in &self.windows {
+            let window_start = *window_start;
+            check_window_start(&mut report, window_start, self.duration, &mut previous);
+            if window.quantiles.len() != len {
+                report.issues.push(ValidationIssue::BucketCountMismatch {
+                    window_start,
+                    expected: len,
+                    actual: window.quantiles.len(),
+                });
+                continue;
+            }
+            let Some(actual) = checked_total(&window.quantiles) else {
+                report
+                    .issues
+                    .push(ValidationIssue::CountOverflow { window_start });
+                continue;
+            };
+            if actual != window.val_count {
+                report.issues.push(ValidationIssue::CountMismatch {
+                    window_start,
+                    recorded: window.val_count,
+                    actual,
+                });
+            }
+            let blocks_match = window
+                .quantiles
+                .chunks(RANK_BLOCK)
+                .zip(&window.block_counts)
+                .all(|(block, &total)| checked_total(block) == Some(total));
+            if !blocks_match || window.block_counts.len() != len.div_ceil(RANK_BLOCK) {
+                report
+                    .issues
+                    .push(ValidationIssue::BlockCountMismatch { window_start });
+            }
+        }
+        if let Some(index) = &self.contributor_index
+            && index.len() != self.contributors.len()
+        {
+            report
+                .issues
+                .push(ValidationIssue::ContributorIndexMismatch {
+                    contributors: self.contributors.len(),
+                    indexed: index.len(),
+                });
+        }
+        report
+    }
+}
+
+/// Checks the configuration and returns the number of counts each window must hold, or
+/// `None` if an issue was found.
+fn check_config(
+    report: &mut ValidationReport,
+    start: u64,
+    end: u64,
+    duration: u64,
+) -> Option<usize> {
+    if duration == 0 {
+        report.issues.push(ValidationIssue::ZeroDuration);
+    }
+    let len = match end.checked_sub(start) {
+        None => {
+            report
+                .issues
+                .push(ValidationIssue::InvalidRange { start, end });
+            None
+        }
+        Some(width) => {
+            let len = width
+                .checked_add(1)
+                .and_then(|len| usize::try_from(len).ok());
+            if len.is_none() {
+                report
+                    .issues
+                    .push(ValidationIssue::RangeTooWide { start, end });
+            }
+            len
+        }
+    };
+    len.filter(|_| duration > 0)
+}
+
+fn checked_total(counts: &[usize]) -> Option<usize> {
+    counts.iter().try_fold(0usize, |sum, &c| sum.checked_add(c))
+}
+
+fn check_window_start(
+    report: &mut ValidationReport,
+    window_start: u64,
+    duration: u64,
+    previous: &mut Option<u64>,
+) {
+    if !window_start.is_multiple_of(duration) {
+        report
+            .issues
+            .push(ValidationIssue::MisalignedWindow { window_start });
+    }
+    if previous.is_some_and(|p| p >= window_start) {
+        report
+            .issues
+            .push(ValidationIssue::UnorderedWindow { window_start });
+    }
+    *previous = Some(window_start);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring_buffer::TimeBasedRingBuffer;
+    #[test]
+    fn test_round_trip_through_parts() {
+        let mut buffer = TimeBasedRingBuffer::new(3, 10, 0, 100);
+        buffer.insert(4, 3).unwrap();
+        buffer.insert(60, 14).unwrap();
+        let snapshot = buffer.snapshot();
+        assert!(snapshot.validate().is_valid());
+        let restored = Snapshot::from_parts(0, 100, 10, snapshot.to_parts()).unwrap();
+        assert!(restored.validate().is_valid());
+        assert_eq!(restored.to_parts(), snapshot.to_parts());
+        assert_eq!(restored.estimate_quantile(1.0).unwrap(), 60);
+    }
+    #[test]
+    fn test_corrupt_parts_are_reported() {
+        let mut counts = vec![0; 101];
+        counts[5] = 2;
+        let windows = vec![
+            WindowParts {
+                start: 20,
+                val_count: 3,
+                counts: counts.clone(),
+            },
+            WindowParts {
+                start: 15,
+                val_count: 2,
+                counts,
+            },
+            WindowParts {
+                start: 30,
+                val_count: 0,
+                counts: vec![0; 7],
+            },
+        ];
+        let report = Snapshot::from_parts(0, 100, 10, windows).unwrap_err();
+        assert_eq!(
+            report.issues,
+            vec![
+                ValidationIssue::CountMismatch {
+                    window_start: 20,
+                    recorded: 3,
+                    actual: 2
+                },
+                ValidationIssue::MisalignedWindow { window_start: 15 },
+                ValidationIssue::UnorderedWindow { window_start: 15 },
+                ValidationIssue::BucketCountMismatch {
+                    window_start: 30,
+                    expected: 101,
+                    actual: 7
+                },
+            ]
+        );
+        assert!(!report.to_string().is_empty());
+        let report = Snapshot::from_parts(0, 100, 0, Vec::new()).unwrap_err();
+        assert_eq!(report.issues, vec![ValidationIssue::ZeroDuration]);
+    }
+    #[test]
+    fn test_overflowing_parts_are_reported() {
+        let report = Snapshot::from_parts(0, u64::MAX, 10, Vec::new()).unwrap_err();
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::RangeTooWide {
+                start: 0,
+                end: u64::MAX
+            }]
+        );
+        // The length fits in a usize on 64-bit targets, but no window can match it
+        let window = WindowParts {
+            start: 0,
+            val_count: 1,
+            counts: vec![0; 2],
+        };
+        assert!(Snapshot::from_parts(1, u64::MAX, 10, vec![window]).is_err());
+
+        let windows = vec![WindowParts {
+            start: 0,
+            val_count: usize::MAX,
+            counts: vec![usize::MAX, usize::MAX, 0],
+        }];
+        let report = Snapshot::from_parts(0, 2, 10, windows).unwrap_err();
+        assert_eq!(
+            report.issues,
+            vec![ValidationIssue::CountOverflow { window_start: 0 }]
+        );
+
+        let mut snapshot = Snapshot::from_parts(0, 2, 10, Vec::new()).unwrap();
+        let mut window = QuantileEstimator::new(0, 2);
+        window.quantiles = vec![usize::MAX, 1, 0];
+        snapshot.windows.push((0, window));
+        assert_eq!(
+            snapshot.validate().issues,
+            vec![ValidationIssue::CountOverflow { window_start: 0 },]
+        );
+        snapshot.end = u64::MAX;
+        assert_eq!(
+            snapshot.validate().issues,
+            vec![ValidationIssue::RangeTooWide {
+                start: 0,
+                end: u64::MAX
+            }]
+        );
+    }
+}
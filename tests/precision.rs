@@ -0,0 +1,158 @@
+//! Checks every query path against the exact nearest-rank quantile of the raw data on
+//! standard synthetic distributions. Buckets have unit width, so the documented error
+//! bound is zero: every backend must return exactly the sorted sample at index
+//! `round(fraction * count) - 1`.
+
+use quantile::testing::SketchComparator;
+use quantile::{
+    QuantileEstimator, TimeBasedRingBuffer, assert_quantiles_close, merge_all, select_quantile,
+};
+
+const RANGE_END: u64 = 10_000;
+const FRACTIONS: [f64; 9] = [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+
+/// Deterministic linear congruential generator, so failures are reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next_unit(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn uniform(rng: &mut Lcg) -> u64 {
+    (rng.next_unit() * RANGE_END as f64) as u64
+}
+
+fn exponential(rng: &mut Lcg) -> u64 {
+    let value = -(1.0 - rng.next_unit()).ln() * 300.0;
+    (value as u64).min(RANGE_END)
+}
+
+fn bimodal(rng: &mut Lcg) -> u64 {
+    let center = if rng.next_unit() < 0.8 { 50.0 } else { 5_000.0 };
+    let jitter = (rng.next_unit() - 0.5) * 40.0;
+    (center + jitter) as u64
+}
+
+type Generator = fn(&mut Lcg) -> u64;
+
+fn distributions() -> Vec<(&'static str, Vec<u64>)> {
+    let generators: [(&str, Generator); 3] = [
+        ("uniform", uniform),
+        ("exponential", exponential),
+        ("bimodal", bimodal),
+    ];
+    generators
+        .iter()
+        .map(|&(name, generate)| {
+            let mut rng = Lcg(42);
+            (name, (0..5_000).map(|_| generate(&mut rng)).collect())
+        })
+        .collect()
+}
+
+fn exact(sorted: &[u64], fraction: f64) -> u64 {
+    let index = ((fraction * sorted.len() as f64).round() as usize).saturating_sub(1);
+    sorted[index]
+}
+
+fn assert_exact(backend: &str, name: &str, sorted: &[u64], estimate: impl Fn(f64) -> u64) {
+    for fraction in FRACTIONS {
+        assert_eq!(
+            estimate(fraction),
+            exact(sorted, fraction),
+            "{backend} on {name} at {fraction}"
+        );
+    }
+}
+
+#[test]
+fn estimator_is_exact() {
+    for (name, values) in distributions() {
+        let mut estimator = QuantileEstimator::new(0, RANGE_END);
+        for &v in &values {
+            estimator.add_value(v).unwrap();
+        }
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        assert_exact("estimator", name, &sorted, |f| {
+            estimator.estimate_quantile(f).unwrap()
+        });
+        let batch = estimator.estimate_quantiles(&FRACTIONS).unwrap();
+        let expected: Vec<u64> = FRACTIONS.iter().map(|&f| exact(&sorted, f)).collect();
+        assert_eq!(batch, expected, "estimate_quantiles on {name}");
+    }
+}
+
+#[test]
+fn ring_buffer_and_snapshot_are_exact() {
+    for (name, values) in distributions() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(8, 1_000, 0, RANGE_END);
+        for (i, &v) in values.iter().enumerate() {
+            ring_buffer.insert(v, i as u64).unwrap();
+        }
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        assert_exact("ring buffer", name, &sorted, |f| {
+            ring_buffer.estimate_quantile(f).unwrap()
+        });
+        let snapshot = ring_buffer.snapshot();
+        assert_exact("snapshot", name, &sorted, |f| {
+            snapshot.estimate_quantile(f).unwrap()
+        });
+    }
+}
+
+#[test]
+fn merged_shards_are_exact() {
+    for (name, values) in distributions() {
+        let mut shards = vec![QuantileEstimator::new(0, RANGE_END); 7];
+        for (i, &v) in values.iter().enumerate() {
+            shards[i % 7].add_value(v).unwrap();
+        }
+        let mut sorted = values.clone();
+        sorted.sort_unstable();
+        let merged = merge_all(&shards).unwrap();
+        assert_exact("merge_all", name, &sorted, |f| {
+            merged.estimate_quantile(f).unwrap()
+        });
+        assert_exact("select_quantile", name, &sorted, |f| {
+            select_quantile(&shards, f).unwrap()
+        });
+    }
+}
+
+#[test]
+fn quantized_snapshots_stay_within_rank_tolerance() {
+    for (name, values) in distributions() {
+        let mut ring_buffer = TimeBasedRingBuffer::new(4, 250, 0, RANGE_END);
+        for (i, &v) in values.iter().enumerate() {
+            ring_buffer.insert(v, i as u64 / 10).unwrap();
+        }
+        let snapshot = ring_buffer.snapshot();
+        let quantized = snapshot.quantized(2);
+        // Two significant digits move each count, and so the number of values below and
+        // above any point, by at most ε = ½·10^(1−2) = 5% of itself. The share of values
+        // on the nearer side of a quantile at `f` then moves by at most
+        // min(f, 1−f)·2ε/(1−ε), plus one value of nearest-rank rounding.
+        let epsilon = 0.5 * 10f64.powi(1 - 2);
+        for fraction in FRACTIONS {
+            let tolerance = fraction.min(1.0 - fraction) * 2.0 * epsilon / (1.0 - epsilon)
+                + 1.0 / values.len() as f64;
+            let comparator = SketchComparator::new(tolerance)
+                .fractions(&[fraction])
+                .unwrap();
+            if let Err(mismatch) = comparator.compare(&snapshot, &quantized) {
+                panic!("{name} quantized at {fraction}: {mismatch}");
+            }
+        }
+        // The bound peaks at the median, covering every percentile
+        let worst = epsilon / (1.0 - epsilon) + 1.0 / values.len() as f64;
+        assert_quantiles_close!(snapshot, quantized, worst);
+    }
+}
@@ -0,0 +1,193 @@
+//! Simulates a week of traffic against a manual clock, with a diurnal load pattern,
+//! deploys that regress latency for an hour, and a traffic outage, then checks rotation,
+//! downsampling, persistence, export and threshold alerting end to end.
+
+use quantile::{
+    ExportFilter, QuantileRegistry, SeriesConfig, Snapshot, TimeBasedRingBuffer, WindowParts,
+};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+const MAX_LATENCY: u64 = 2_000;
+/// Deploys at 14:00 on days 2, 4 and 7, each adding 300 ms for the following hour.
+const DEPLOYS: [u64; 3] = [DAY + 14 * HOUR, 3 * DAY + 14 * HOUR, 6 * DAY + 14 * HOUR];
+/// No traffic at all between 02:00 and 05:00 on the last day.
+const OUTAGE: (u64, u64) = (6 * DAY + 2 * HOUR, 6 * DAY + 5 * HOUR);
+const API: &str = "latency{route=\"/api\"}";
+const STATIC: &str = "latency{route=\"/static\"}";
+
+/// Deterministic linear congruential generator, so failures are reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        self.0 >> 33
+    }
+}
+
+/// Latency in milliseconds: slower at the daily peak around 12:00, with jitter and the
+/// deploy regressions.
+fn latency(rng: &mut Lcg, now: u64) -> u64 {
+    let hour = now % DAY / HOUR;
+    let load = 12 - hour.abs_diff(12);
+    let regression = if DEPLOYS.iter().any(|&d| (d..d + HOUR).contains(&now)) {
+        300
+    } else {
+        0
+    };
+    (40 + 5 * load + rng.next() % 50 + regression).min(MAX_LATENCY)
+}
+
+/// Seconds between requests: busier during the day.
+fn gap(now: u64) -> u64 {
+    if (8 * HOUR..20 * HOUR).contains(&(now % DAY)) {
+        5
+    } else {
+        20
+    }
+}
+
+struct Simulation {
+    registry: QuantileRegistry,
+    /// The `/api` series again, with deploy annotations, as kept by the service itself.
+    api: TimeBasedRingBuffer,
+    /// Raw `/api` latencies of the last day, for exact comparisons.
+    last_day: Vec<(u64, u64)>,
+}
+
+fn simulate() -> Simulation {
+    let config = SeriesConfig {
+        capacity: (DAY / MINUTE) as usize,
+        duration: MINUTE,
+        start: 0,
+        end: MAX_LATENCY,
+    };
+    let mut simulation = Simulation {
+        registry: QuantileRegistry::builder(config).build(),
+        api: TimeBasedRingBuffer::new(config.capacity, MINUTE, 0, MAX_LATENCY),
+        last_day: Vec::new(),
+    };
+    let mut rng = Lcg(2024);
+    let mut deploys = DEPLOYS.iter().peekable();
+    let mut now = 0;
+    while now < WEEK {
+        if (OUTAGE.0..OUTAGE.1).contains(&now) {
+            now = OUTAGE.1;
+            continue;
+        }
+        let value = latency(&mut rng, now);
+        simulation.registry.record(API, value, now).unwrap();
+        simulation.registry.record(STATIC, value / 4, now).unwrap();
+        simulation.api.insert(value, now).unwrap();
+        if let Some(&&deploy) = deploys.peek()
+            && deploy <= now
+        {
+            simulation.api.annotate(deploy, "deploy");
+            deploys.next();
+        }
+        if now >= WEEK - DAY {
+            simulation.last_day.push((now, value));
+        }
+        now += gap(now);
+    }
+    simulation
+}
+
+fn exact_quantile(values: &mut [u64], fraction: f64) -> u64 {
+    values.sort_unstable();
+    let index = ((fraction * values.len() as f64).round() as usize).saturating_sub(1);
+    values[index]
+}
+
+#[test]
+fn test_week_of_traffic() {
+    let simulation = simulate();
+    let api = &simulation.api;
+
+    // Rotation: exactly the last day is retained, and annotations older than that are gone
+    let starts: Vec<u64> = api.windows().map(|(ts, _)| ts).collect();
+    assert_eq!(starts.len(), (DAY / MINUTE) as usize);
+    assert_eq!(starts[0], WEEK - DAY);
+    assert_eq!(api.annotations().len(), 1);
+    assert_eq!(api.annotations()[0].timestamp, DEPLOYS[2]);
+
+    // The retained windows answer exactly what the raw last day holds
+    let mut raw: Vec<u64> = simulation.last_day.iter().map(|&(_, v)| v).collect();
+    for fraction in [0.5, 0.9, 0.99, 1.0] {
+        assert_eq!(
+            api.estimate_quantile(fraction).unwrap(),
+            exact_quantile(&mut raw, fraction)
+        );
+    }
+    assert!(
+        api.estimate_quantile_between(0.5, OUTAGE.0, OUTAGE.1)
+            .is_err()
+    );
+
+    // Downsampling: hourly windows keep every value, and the outage hours have none
+    let hourly = api.resample(HOUR).unwrap();
+    assert_eq!(hourly.windows().len(), 24 - 3);
+    let total: usize = hourly
+        .windows()
+        .iter()
+        .map(|(_, w)| w.rank(MAX_LATENCY))
+        .sum();
+    assert_eq!(total, simulation.last_day.len());
+    assert_eq!(hourly.estimate_quantile(0.99), api.estimate_quantile(0.99));
+
+    // Alerting: the only hourly p99 above 350 ms is the deploy hour, which carries the
+    // deploy annotation
+    let bands = hourly.bands(0.5, 0.9, 0.99).unwrap();
+    let alerts: Vec<_> = bands.iter().filter(|b| b.high > 350).collect();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].start, DEPLOYS[2]);
+    assert_eq!(alerts[0].annotations[0].text, "deploy");
+    let mut deploy_hour: Vec<u64> = simulation
+        .last_day
+        .iter()
+        .filter(|&&(ts, _)| (DEPLOYS[2]..DEPLOYS[2] + HOUR).contains(&ts))
+        .map(|&(_, v)| v)
+        .collect();
+    assert_eq!(alerts[0].high, exact_quantile(&mut deploy_hour, 0.99));
+
+    // Persistence: the snapshot survives a round trip through its raw parts
+    let snapshot = api.snapshot();
+    let parts: Vec<WindowParts> = snapshot.to_parts();
+    let restored = Snapshot::from_parts(0, MAX_LATENCY, MINUTE, parts).unwrap();
+    assert!(restored.validate().is_valid());
+    for fraction in [0.0, 0.5, 0.999] {
+        assert_eq!(
+            restored.estimate_quantile(fraction),
+            snapshot.estimate_quantile(fraction)
+        );
+    }
+
+    // Export: the registry's /api series matches the service's own buffer
+    let text = simulation
+        .registry
+        .prometheus_summary(&[0.99], &ExportFilter::new().include("route", "/api"))
+        .unwrap();
+    let p99 = api.estimate_quantile(0.99).unwrap();
+    assert!(text.contains(&format!(
+        "latency{{route=\"/api\",quantile=\"0.99\"}} {p99}\n"
+    )));
+    assert!(text.contains(&format!(
+        "latency_count{{route=\"/api\"}} {}\n",
+        simulation.last_day.len()
+    )));
+    let lines = simulation
+        .registry
+        .json_lines(&[0.5], &ExportFilter::new())
+        .unwrap();
+    let non_empty = api
+        .windows()
+        .filter(|(_, w)| w.rank(MAX_LATENCY) > 0)
+        .count();
+    assert_eq!(lines.lines().count(), 2 * non_empty);
+}